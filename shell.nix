@@ -5,6 +5,9 @@ pkgs.mkShell {
     rustc
     cargo
     poppler_utils
+    mupdf
+    ghostscript
+    tesseract
     yt-dlp
     ffmpeg
     jq
@@ -12,12 +15,14 @@ pkgs.mkShell {
 
   shellHook = ''
     echo "Development environment loaded with:"
-    echo "  - Rust and poppler-utils for PDF extraction"
+    echo "  - Rust, poppler-utils, mupdf and ghostscript for PDF extraction"
+    echo "  - tesseract for OCR (extract --ocr)"
     echo "  - yt-dlp, ffmpeg, and jq for YouTube downloads"
     echo ""
     echo "Available commands:"
     echo "  just setup          - Build the Rust binary"
     echo "  just extract        - Extract PDFs with parallel processing"
+    echo "  just extract-ocr    - Extract PDFs and OCR pages without a text layer"
     echo "  just download-youtube - Download YouTube videos and extract MP3s"
     echo "  just count          - Count PDF files"
     echo "  just count-pngs     - Count extracted PNG files"
@@ -0,0 +1,180 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use image::{DynamicImage, GenericImageView};
+use rayon::prelude::*;
+use std::path::Path;
+
+use crate::manifest::BlankPage;
+use crate::pages;
+
+// Pixels darker than this (0-255 luma) count as ink; paper texture and scanner
+// noise on an empty sheet stay well above it
+const INK_LEVEL: u8 = 128;
+
+// Scans often have dark bands along the edges where the scanner lid didn't
+// cover the sheet, so this fraction of each side is left out of the measurement
+const EDGE_MARGIN: f64 = 0.05;
+
+// Where --blank-action move puts near-blank pages, next to the document's pages
+pub const REJECTED_DIR: &str = "rejected";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BlankAction {
+    /// Delete near-blank pages
+    Skip,
+    /// Keep near-blank pages, listing them as blank in the manifest
+    Tag,
+    /// Move near-blank pages into a rejected/ subfolder
+    Move,
+}
+
+impl BlankAction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BlankAction::Skip => "skip",
+            BlankAction::Tag => "tag",
+            BlankAction::Move => "move",
+        }
+    }
+
+    // What happened to the pages, for log lines
+    pub fn description(&self) -> &'static str {
+        match self {
+            BlankAction::Skip => "skipped",
+            BlankAction::Tag => "tagged",
+            BlankAction::Move => "moved to rejected/",
+        }
+    }
+}
+
+// Parse a --blank-threshold value, a fraction of the page between 0 and 1
+pub fn parse_threshold(value: &str) -> std::result::Result<f64, String> {
+    let threshold: f64 = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid threshold '{}'", value))?;
+    if !(0.0..=1.0).contains(&threshold) {
+        return Err(format!("threshold must be between 0 and 1, got {}", threshold));
+    }
+    Ok(threshold)
+}
+
+// Fraction of a page image's pixels that are ink, from 0.0 (empty) to 1.0
+pub fn ink_coverage(path: &Path) -> Result<f64> {
+    let image = image::open(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(image_ink_coverage(&image))
+}
+
+fn image_ink_coverage(image: &DynamicImage) -> f64 {
+    let (width, height) = image.dimensions();
+    let (margin_x, margin_y) = ((width as f64 * EDGE_MARGIN) as u32, (height as f64 * EDGE_MARGIN) as u32);
+    let inner = image
+        .crop_imm(margin_x, margin_y, width - 2 * margin_x, height - 2 * margin_y)
+        .to_luma8();
+
+    let total = inner.width() as u64 * inner.height() as u64;
+    if total == 0 {
+        return 0.0;
+    }
+    let ink = inner.pixels().filter(|pixel| pixel.0[0] < INK_LEVEL).count() as u64;
+    ink as f64 / total as f64
+}
+
+// Measure every page rendered into `dir` and skip, tag or move the ones with
+// less ink than `threshold`. Pages we can't decode ourselves (JBIG2, JPEG 2000)
+// are left alone. Returns the blank pages, with where their file ended up.
+pub fn handle_blank_pages(
+    dir: &Path,
+    pdf_name: &str,
+    page_count: usize,
+    threshold: f64,
+    action: BlankAction,
+) -> Result<Vec<BlankPage>> {
+    let mut blank: Vec<(usize, std::path::PathBuf, f64)> = (1..=page_count)
+        .into_par_iter()
+        .filter_map(|page| {
+            let path = pages::find_page_file(dir, pdf_name, page, page_count)?;
+            let coverage = ink_coverage(&path).ok()?;
+            (coverage < threshold).then_some((page, path, coverage))
+        })
+        .collect();
+    blank.sort_by_key(|(page, _, _)| *page);
+
+    let mut blank_pages = Vec::new();
+    for (page, path, ink_coverage) in blank {
+        let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+        let file = match action {
+            BlankAction::Skip => {
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("Failed to remove blank page {}", path.display()))?;
+                None
+            }
+            BlankAction::Tag => Some(file_name),
+            BlankAction::Move => {
+                let rejected_dir = dir.join(REJECTED_DIR);
+                std::fs::create_dir_all(&rejected_dir)
+                    .with_context(|| format!("Failed to create {}", rejected_dir.display()))?;
+                let destination = rejected_dir.join(&file_name);
+                std::fs::rename(&path, &destination)
+                    .with_context(|| format!("Failed to move {} to {}", path.display(), destination.display()))?;
+                Some(format!("{}/{}", REJECTED_DIR, file_name))
+            }
+        };
+        blank_pages.push(BlankPage { page, ink_coverage, file });
+    }
+
+    Ok(blank_pages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+
+    // An off-white 200x200 page with light scanner noise
+    fn paper() -> GrayImage {
+        GrayImage::from_fn(200, 200, |x, y| Luma([230 + ((x * 7 + y * 13) % 20) as u8]))
+    }
+
+    fn coverage(page: GrayImage) -> f64 {
+        image_ink_coverage(&DynamicImage::ImageLuma8(page))
+    }
+
+    #[test]
+    fn empty_page_has_no_ink() {
+        assert_eq!(coverage(paper()), 0.0);
+    }
+
+    #[test]
+    fn a_little_ink_is_measured() {
+        let mut page = paper();
+        // A 20x9 stamp in the middle: 180 of the 180x180 measured pixels
+        for x in 90..110 {
+            for y in 95..104 {
+                page.put_pixel(x, y, Luma([20]));
+            }
+        }
+        let ink = coverage(page);
+        assert!((ink - 180.0 / (180.0 * 180.0)).abs() < 1e-9, "{}", ink);
+        assert!(ink < 0.01);
+    }
+
+    #[test]
+    fn dark_scanner_edges_are_ignored() {
+        let mut page = paper();
+        for y in 0..200 {
+            for x in 0..8 {
+                page.put_pixel(x, y, Luma([0]));
+                page.put_pixel(199 - x, y, Luma([0]));
+            }
+        }
+        assert_eq!(coverage(page), 0.0);
+    }
+
+    #[test]
+    fn threshold_must_be_a_fraction() {
+        assert_eq!(parse_threshold("0.005"), Ok(0.005));
+        assert!(parse_threshold("1.5").is_err());
+        assert!(parse_threshold("blank").is_err());
+    }
+}
@@ -0,0 +1,203 @@
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+
+use crate::staging;
+use crate::watch::is_pdf;
+
+// Archive mirrors distributed as a single file, which we read PDFs out of
+// without unpacking the whole thing first
+enum BundleKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+fn bundle_kind(path: &Path) -> Option<BundleKind> {
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+    if name.ends_with(".zip") {
+        Some(BundleKind::Zip)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(BundleKind::TarGz)
+    } else if name.ends_with(".tar") {
+        Some(BundleKind::Tar)
+    } else {
+        None
+    }
+}
+
+pub fn is_bundle(path: &Path) -> bool {
+    path.is_file() && bundle_kind(path).is_some()
+}
+
+// A temporary copy of a bundle entry; the copy is deleted when this is dropped
+pub struct EntryCopy {
+    dir: PathBuf,
+}
+
+impl Drop for EntryCopy {
+    fn drop(&mut self) {
+        staging::discard(&self.dir);
+    }
+}
+
+// A PDF copied out of a bundle
+pub struct BundleEntry {
+    // The bundle's path joined with the entry's path inside it, which is what
+    // output names, manifests and reports use
+    pub path: PathBuf,
+    // The temporary copy to render from
+    pub file: PathBuf,
+    pub copy: EntryCopy,
+}
+
+// A PDF entry that couldn't be copied out of the bundle: corrupt data, an
+// encrypted zip entry, or a compression method other than stored or deflate
+pub struct EntryFailure {
+    pub path: PathBuf,
+    pub error: anyhow::Error,
+}
+
+impl EntryFailure {
+    // Reports print errors without their causes, so fold the cause (which says
+    // what was wrong with the entry) into the message
+    fn new(path: PathBuf, error: anyhow::Error) -> Self {
+        Self { path, error: anyhow::anyhow!("{:#}", error) }
+    }
+}
+
+// An entry's path inside the bundle, or None for names that would escape it
+// ("../x.pdf", "/etc/x.pdf")
+fn inner_path(name: &Path) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for component in name.components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    (!path.as_os_str().is_empty()).then_some(path)
+}
+
+fn open(bundle: &Path) -> Result<BufReader<File>> {
+    let file = File::open(bundle).with_context(|| format!("Failed to open {}", bundle.display()))?;
+    Ok(BufReader::new(file))
+}
+
+// Every PDF in a bundle, as the bundle's path joined with the entry's path
+pub fn list_pdfs(bundle: &Path) -> Result<Vec<PathBuf>> {
+    let mut pdf_files = Vec::new();
+    let mut add = |name: &Path| {
+        if let Some(inner) = inner_path(name).filter(|inner| is_pdf(inner)) {
+            pdf_files.push(bundle.join(inner));
+        }
+    };
+
+    match bundle_kind(bundle).context("Not a zip or tar bundle")? {
+        BundleKind::Zip => {
+            let mut archive = zip::ZipArchive::new(open(bundle)?)
+                .with_context(|| format!("Failed to read {}", bundle.display()))?;
+            for index in 0..archive.len() {
+                let entry = archive.by_index_raw(index)?;
+                if entry.is_file() {
+                    add(Path::new(entry.name()));
+                }
+            }
+        }
+        BundleKind::Tar => list_tar(tar::Archive::new(open(bundle)?), &mut add)?,
+        BundleKind::TarGz => list_tar(tar::Archive::new(GzDecoder::new(open(bundle)?)), &mut add)?,
+    }
+
+    pdf_files.sort();
+    Ok(pdf_files)
+}
+
+fn list_tar<R: Read>(mut archive: tar::Archive<R>, add: &mut impl FnMut(&Path)) -> Result<()> {
+    for entry in archive.entries().context("Failed to read tar bundle")? {
+        let entry = entry.context("Failed to read tar bundle")?;
+        if entry.header().entry_type().is_file() {
+            add(&entry.path()?);
+        }
+    }
+    Ok(())
+}
+
+// Copy one entry to "{output_dir}/.staging/.bundle-N/{file name}". Keeping the
+// file name means log lines and rasterizer errors still name the right document.
+fn copy_entry(bundle: &Path, inner: &Path, reader: &mut impl Read, output_dir: &Path, index: usize) -> Result<BundleEntry> {
+    let dir = staging::prepare(output_dir, &format!(".bundle-{}", index))?;
+    let copy = EntryCopy { dir };
+    let file = copy.dir.join(inner.file_name().context("Bundle entry has no file name")?);
+
+    let mut writer = File::create(&file).with_context(|| format!("Failed to create {}", file.display()))?;
+    std::io::copy(reader, &mut writer)
+        .with_context(|| format!("Failed to copy {} out of {}", inner.display(), bundle.display()))?;
+
+    Ok(BundleEntry { path: bundle.join(inner), file, copy })
+}
+
+// Copy the PDFs out of a bundle one at a time, in the order they are stored, and
+// hand each to `on_entry`, stopping early if it returns false. Only the copies
+// `on_entry` is still holding on to take up disk space. An entry that can't be
+// read is handed over as an EntryFailure and the rest are still copied out.
+pub fn for_each_pdf(
+    bundle: &Path,
+    output_dir: &Path,
+    mut on_entry: impl FnMut(std::result::Result<BundleEntry, EntryFailure>) -> bool,
+) -> Result<()> {
+    match bundle_kind(bundle).context("Not a zip or tar bundle")? {
+        BundleKind::Zip => {
+            let mut archive = zip::ZipArchive::new(open(bundle)?)
+                .with_context(|| format!("Failed to read {}", bundle.display()))?;
+            for index in 0..archive.len() {
+                // The raw entry only needs the headers, so it names the entry
+                // even when its data can't be decompressed
+                let inner = {
+                    let entry = archive.by_index_raw(index)?;
+                    inner_path(Path::new(entry.name())).filter(|inner| entry.is_file() && is_pdf(inner))
+                };
+                let Some(inner) = inner else {
+                    continue;
+                };
+                let entry = archive
+                    .by_index(index)
+                    .with_context(|| format!("Failed to read {} in {}", inner.display(), bundle.display()))
+                    .and_then(|mut entry| copy_entry(bundle, &inner, &mut entry, output_dir, index));
+                let entry = entry.map_err(|error| EntryFailure::new(bundle.join(&inner), error));
+                if !on_entry(entry) {
+                    break;
+                }
+            }
+            Ok(())
+        }
+        BundleKind::Tar => for_each_tar_pdf(bundle, tar::Archive::new(open(bundle)?), output_dir, on_entry),
+        BundleKind::TarGz => for_each_tar_pdf(bundle, tar::Archive::new(GzDecoder::new(open(bundle)?)), output_dir, on_entry),
+    }
+}
+
+fn for_each_tar_pdf<R: Read>(
+    bundle: &Path,
+    mut archive: tar::Archive<R>,
+    output_dir: &Path,
+    mut on_entry: impl FnMut(std::result::Result<BundleEntry, EntryFailure>) -> bool,
+) -> Result<()> {
+    for (index, entry) in archive.entries().context("Failed to read tar bundle")?.enumerate() {
+        // A damaged header ends the tar stream, as nothing after it can be found
+        let mut entry = entry.context("Failed to read tar bundle")?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let Some(inner) = inner_path(&entry.path()?).filter(|inner| is_pdf(inner)) else {
+            continue;
+        };
+        let entry = copy_entry(bundle, &inner, &mut entry, output_dir, index)
+            .map_err(|error| EntryFailure::new(bundle.join(&inner), error));
+        if !on_entry(entry) {
+            break;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,312 @@
+use anyhow::{Context, Result};
+pub use rusqlite::types::Value;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use std::path::{Path, PathBuf};
+
+// Written to the output directory unless --catalog names another file
+pub const DEFAULT_FILE: &str = "catalog.sqlite";
+
+// Bumped whenever the tables below change
+const SCHEMA_VERSION: i64 = 1;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS documents (
+    id INTEGER PRIMARY KEY,
+    source_path TEXT NOT NULL UNIQUE,
+    source_size INTEGER,
+    source_sha256 TEXT,
+    page_count INTEGER,
+    -- found, then the status of the last extraction: processed, repaired,
+    -- updated, skipped, failed, timeout or encrypted
+    status TEXT NOT NULL,
+    error TEXT,
+    manifest_path TEXT,
+    -- Lets unchanged manifests be skipped when syncing pages
+    manifest_mtime_ns INTEGER,
+    found_at_unix INTEGER NOT NULL,
+    extracted_at_unix INTEGER,
+    updated_at_unix INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS pages (
+    id INTEGER PRIMARY KEY,
+    document_id INTEGER NOT NULL REFERENCES documents(id) ON DELETE CASCADE,
+    page INTEGER NOT NULL,
+    -- ok, blank (under --blank-threshold) or duplicate (removed by --dedupe remove)
+    status TEXT NOT NULL,
+    -- Absolute path of the image, NULL for pages that were deleted
+    file TEXT,
+    width INTEGER,
+    height INTEGER,
+    phash TEXT,
+    -- The text layer, or the OCR text for pages without one
+    text TEXT,
+    text_source TEXT,
+    classification TEXT,
+    -- The page's .tags.json, when --tags ran
+    tags TEXT,
+    redaction_coverage REAL,
+    redaction_boxes INTEGER,
+    -- How --deskew and --trim changed the page, as JSON
+    transform TEXT,
+    UNIQUE (document_id, page)
+);
+CREATE INDEX IF NOT EXISTS pages_file ON pages (file);
+CREATE INDEX IF NOT EXISTS pages_classification ON pages (classification);
+
+CREATE TABLE IF NOT EXISTS videos (
+    id INTEGER PRIMARY KEY,
+    output_path TEXT NOT NULL,
+    -- The selection the pages were drawn from
+    selection TEXT,
+    created_at_unix INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS video_pages (
+    video_id INTEGER NOT NULL REFERENCES videos(id) ON DELETE CASCADE,
+    page_id INTEGER NOT NULL REFERENCES pages(id) ON DELETE CASCADE,
+    PRIMARY KEY (video_id, page_id)
+);
+";
+
+// A document's row, as of its latest extraction
+pub struct DocumentRecord<'a> {
+    pub source_path: &'a str,
+    pub source_size: u64,
+    pub source_sha256: &'a str,
+    pub page_count: usize,
+    pub manifest_path: &'a str,
+    pub manifest_mtime_ns: Option<i64>,
+    pub extracted_at_unix: u64,
+}
+
+pub struct PageRecord {
+    pub page: usize,
+    pub status: &'static str,
+    pub file: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub phash: Option<String>,
+    pub text: Option<String>,
+    pub text_source: Option<&'static str>,
+    pub classification: Option<String>,
+    pub tags: Option<String>,
+    pub redaction_coverage: Option<f64>,
+    pub redaction_boxes: Option<usize>,
+    pub transform: Option<String>,
+}
+
+impl PageRecord {
+    // A page with only its number, status and file known
+    pub fn new(page: usize, status: &'static str, file: Option<String>) -> Self {
+        Self {
+            page,
+            status,
+            file,
+            width: None,
+            height: None,
+            phash: None,
+            text: None,
+            text_source: None,
+            classification: None,
+            tags: None,
+            redaction_coverage: None,
+            redaction_boxes: None,
+            transform: None,
+        }
+    }
+}
+
+// The catalog database. Extract records documents and pages in it;
+// generate-video selects pages from it and records the videos it made.
+pub struct Catalog {
+    connection: Connection,
+}
+
+impl Catalog {
+    pub fn open(path: &Path) -> Result<Self> {
+        let connection = Connection::open(path).with_context(|| format!("Failed to open catalog {}", path.display()))?;
+        // Both binaries may have the catalog open at once
+        connection.busy_timeout(std::time::Duration::from_secs(30))?;
+        connection.execute_batch("PRAGMA journal_mode = WAL; PRAGMA foreign_keys = ON;")?;
+
+        let version: i64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version > SCHEMA_VERSION {
+            return Err(anyhow::anyhow!(
+                "catalog {} was written by a newer version (schema {}, expected {})",
+                path.display(),
+                version,
+                SCHEMA_VERSION
+            ));
+        }
+        connection
+            .execute_batch(SCHEMA)
+            .with_context(|| format!("Failed to create tables in {}", path.display()))?;
+        connection.execute_batch(&format!("PRAGMA user_version = {}", SCHEMA_VERSION))?;
+        Ok(Self { connection })
+    }
+
+    // Open an existing catalog for selecting pages only, leaving its schema and
+    // journal mode alone; SQLite refuses any write made through it
+    pub fn open_read_only(path: &Path) -> Result<Self> {
+        let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("Failed to open catalog {}", path.display()))?;
+        connection.busy_timeout(std::time::Duration::from_secs(30))?;
+
+        let version: i64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version != SCHEMA_VERSION {
+            return Err(anyhow::anyhow!(
+                "catalog {} has schema {}, expected {} (run `extract` to update it)",
+                path.display(),
+                version,
+                SCHEMA_VERSION
+            ));
+        }
+        Ok(Self { connection })
+    }
+
+    // Add documents listed in the archive that the catalog doesn't know yet
+    pub fn record_found(&mut self, source_paths: &[String], now_unix: u64) -> Result<()> {
+        let transaction = self.connection.transaction()?;
+        {
+            let mut insert = transaction.prepare(
+                "INSERT OR IGNORE INTO documents (source_path, status, found_at_unix, updated_at_unix)
+                 VALUES (?1, 'found', ?2, ?2)",
+            )?;
+            for source_path in source_paths {
+                insert.execute(params![source_path, now_unix as i64])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    // Set a document's status and error after an extraction attempt
+    pub fn record_status(&mut self, source_path: &str, status: &str, error: Option<&str>, now_unix: u64) -> Result<()> {
+        self.connection.execute(
+            "INSERT INTO documents (source_path, status, error, found_at_unix, updated_at_unix)
+             VALUES (?1, ?2, ?3, ?4, ?4)
+             ON CONFLICT (source_path) DO UPDATE SET
+                 status = excluded.status, error = excluded.error, updated_at_unix = excluded.updated_at_unix",
+            params![source_path, status, error, now_unix as i64],
+        )?;
+        Ok(())
+    }
+
+    // The manifest modification time the document's pages were last synced from
+    pub fn manifest_mtime_ns(&self, source_path: &str) -> Result<Option<i64>> {
+        let mtime = self
+            .connection
+            .query_row(
+                "SELECT manifest_mtime_ns FROM documents WHERE source_path = ?1",
+                params![source_path],
+                |row| row.get::<_, Option<i64>>(0),
+            )
+            .optional()?;
+        Ok(mtime.flatten())
+    }
+
+    // Store a document's details from its manifest and replace its pages
+    pub fn record_document(&mut self, document: &DocumentRecord, pages: &[PageRecord], now_unix: u64) -> Result<()> {
+        let transaction = self.connection.transaction()?;
+        let document_id: i64 = transaction.query_row(
+            "INSERT INTO documents (source_path, source_size, source_sha256, page_count, status, manifest_path,
+                                    manifest_mtime_ns, found_at_unix, extracted_at_unix, updated_at_unix)
+             VALUES (?1, ?2, ?3, ?4, 'processed', ?5, ?6, ?7, ?8, ?7)
+             ON CONFLICT (source_path) DO UPDATE SET
+                 source_size = excluded.source_size, source_sha256 = excluded.source_sha256,
+                 page_count = excluded.page_count, manifest_path = excluded.manifest_path,
+                 manifest_mtime_ns = excluded.manifest_mtime_ns, extracted_at_unix = excluded.extracted_at_unix,
+                 updated_at_unix = excluded.updated_at_unix
+             RETURNING id",
+            params![
+                document.source_path,
+                document.source_size as i64,
+                document.source_sha256,
+                document.page_count as i64,
+                document.manifest_path,
+                document.manifest_mtime_ns,
+                now_unix as i64,
+                document.extracted_at_unix as i64,
+            ],
+            |row| row.get(0),
+        )?;
+
+        // Pages keep their ids across syncs, so videos keep pointing at them
+        let pages_now: Vec<i64> = pages.iter().map(|page| page.page as i64).collect();
+        transaction.execute(
+            "DELETE FROM pages WHERE document_id = ?1 AND page NOT IN (SELECT value FROM json_each(?2))",
+            params![document_id, serde_json::to_string(&pages_now)?],
+        )?;
+        {
+            let mut upsert = transaction.prepare(
+                "INSERT INTO pages (document_id, page, status, file, width, height, phash, text, text_source,
+                                    classification, tags, redaction_coverage, redaction_boxes, transform)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
+                 ON CONFLICT (document_id, page) DO UPDATE SET
+                     status = excluded.status, file = excluded.file, width = excluded.width,
+                     height = excluded.height, phash = excluded.phash, text = excluded.text,
+                     text_source = excluded.text_source, classification = excluded.classification,
+                     tags = excluded.tags, redaction_coverage = excluded.redaction_coverage,
+                     redaction_boxes = excluded.redaction_boxes, transform = excluded.transform",
+            )?;
+            for page in pages {
+                upsert.execute(params![
+                    document_id,
+                    page.page as i64,
+                    page.status,
+                    page.file,
+                    page.width,
+                    page.height,
+                    page.phash,
+                    page.text,
+                    page.text_source,
+                    page.classification,
+                    page.tags,
+                    page.redaction_coverage,
+                    page.redaction_boxes.map(|boxes| boxes as i64),
+                    page.transform,
+                ])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    // Image files of the pages matching an SQL condition over the pages and
+    // documents tables, e.g. "classification = 'TOP SECRET'". Blank and deleted
+    // pages are never selected.
+    pub fn select_pages(&self, condition: &str, values: Vec<Value>) -> Result<Vec<PathBuf>> {
+        let query = format!(
+            "SELECT pages.file FROM pages JOIN documents ON documents.id = pages.document_id
+             WHERE pages.status = 'ok' AND pages.file IS NOT NULL AND ({})
+             ORDER BY pages.file",
+            condition
+        );
+        let mut select = self.connection.prepare(&query).with_context(|| format!("Invalid page query: {}", condition))?;
+        let files = select
+            .query_map(rusqlite::params_from_iter(values), |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(files.into_iter().map(PathBuf::from).collect())
+    }
+
+    // Record a video and the pages it showed
+    pub fn record_video(&mut self, output_path: &Path, selection: &str, pages: &[PathBuf], now_unix: u64) -> Result<()> {
+        let transaction = self.connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO videos (output_path, selection, created_at_unix) VALUES (?1, ?2, ?3)",
+            params![output_path.to_string_lossy(), selection, now_unix as i64],
+        )?;
+        let video_id = transaction.last_insert_rowid();
+        {
+            let mut insert = transaction.prepare(
+                "INSERT OR IGNORE INTO video_pages (video_id, page_id) SELECT ?1, id FROM pages WHERE file = ?2",
+            )?;
+            for page in pages {
+                insert.execute(params![video_id, page.to_string_lossy()])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,27 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+
+// Open a page image in any format we write. The image crate only decodes the
+// luma channel of lossy WebP, so WebP goes through libwebp instead.
+pub fn open_image(path: &Path) -> Result<image::DynamicImage> {
+    let is_webp = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase() == "webp")
+        .unwrap_or(false);
+
+    if is_webp {
+        let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let decoded = webp::Decoder::new(&data)
+            .decode()
+            .with_context(|| format!("Failed to decode WebP image {}", path.display()))?;
+        let (width, height) = (decoded.width(), decoded.height());
+        let img = if decoded.is_alpha() {
+            image::RgbaImage::from_raw(width, height, decoded.to_vec()).map(image::DynamicImage::ImageRgba8)
+        } else {
+            image::RgbImage::from_raw(width, height, decoded.to_vec()).map(image::DynamicImage::ImageRgb8)
+        };
+        return img.context("WebP image has unexpected size");
+    }
+
+    image::open(path).with_context(|| format!("Failed to read {}", path.display()))
+}
@@ -0,0 +1,476 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use image::imageops::FilterType;
+use image::DynamicImage;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use crate::manifest::{self, DuplicatePage};
+
+// Written to the output directory by every dedupe pass
+pub const REPORT_FILE: &str = "duplicates.json";
+
+// Pages are shrunk to (HASH_SIZE + 1) x HASH_SIZE pixels before hashing, giving
+// a 256-bit hash. The common 64-bit version is too coarse for text pages: two
+// different memos on the same letterhead blur into nearly the same 9x8 image.
+const HASH_SIZE: u32 = 16;
+const HASH_WORDS: usize = (HASH_SIZE * HASH_SIZE) as usize / 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DedupeAction {
+    /// Only write the duplicates report
+    Report,
+    /// Replace duplicates with hard links to the page that is kept
+    Hardlink,
+    /// Delete duplicates, keeping one page of each group
+    Remove,
+}
+
+impl DedupeAction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DedupeAction::Report => "report",
+            DedupeAction::Hardlink => "hardlink",
+            DedupeAction::Remove => "remove",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            DedupeAction::Report => "reported",
+            DedupeAction::Hardlink => "hard-linked",
+            DedupeAction::Remove => "removed",
+        }
+    }
+}
+
+// Difference hash: one bit per pixel of the shrunken grayscale page, set when
+// the pixel is brighter than its right-hand neighbour. It survives rescaling,
+// recompression and small rendering differences, while unrelated pages differ
+// in about half their bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct PageHash([u64; HASH_WORDS]);
+
+impl PageHash {
+    fn of_image(path: &Path) -> Result<Self> {
+        let image = image::open(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Ok(Self::of_pixels(&image))
+    }
+
+    fn of_pixels(image: &DynamicImage) -> Self {
+        let small = image.resize_exact(HASH_SIZE + 1, HASH_SIZE, FilterType::Triangle).to_luma8();
+
+        let mut words = [0u64; HASH_WORDS];
+        for y in 0..HASH_SIZE {
+            for x in 0..HASH_SIZE {
+                let bit = (y * HASH_SIZE + x) as usize;
+                if small.get_pixel(x, y).0[0] > small.get_pixel(x + 1, y).0[0] {
+                    words[bit / 64] |= 1 << (bit % 64);
+                }
+            }
+        }
+        Self(words)
+    }
+
+    fn distance(&self, other: &Self) -> u32 {
+        self.0.iter().zip(&other.0).map(|(a, b)| (a ^ b).count_ones()).sum()
+    }
+
+    fn to_hex(self) -> String {
+        self.0.iter().map(|word| format!("{:016x}", word)).collect()
+    }
+
+    fn from_hex(hex: &str) -> Option<Self> {
+        if hex.len() != HASH_WORDS * 16 {
+            return None;
+        }
+        let mut words = [0u64; HASH_WORDS];
+        for (index, word) in words.iter_mut().enumerate() {
+            *word = u64::from_str_radix(hex.get(index * 16..(index + 1) * 16)?, 16).ok()?;
+        }
+        Some(Self(words))
+    }
+}
+
+// Metric tree over the distinct hashes, so finding every hash within a distance
+// of another doesn't mean comparing all pairs of pages
+struct BkTree {
+    nodes: Vec<BkNode>,
+}
+
+struct BkNode {
+    hash: PageHash,
+    // Index into the caller's list of hashes
+    item: usize,
+    children: HashMap<u32, usize>,
+}
+
+impl BkTree {
+    fn new() -> Self {
+        Self { nodes: Vec::new() }
+    }
+
+    // Hashes must be distinct
+    fn insert(&mut self, hash: PageHash, item: usize) {
+        let new_node = self.nodes.len();
+        self.nodes.push(BkNode { hash, item, children: HashMap::new() });
+        if new_node == 0 {
+            return;
+        }
+
+        let mut node = 0;
+        loop {
+            let distance = self.nodes[node].hash.distance(&hash);
+            match self.nodes[node].children.get(&distance) {
+                Some(&child) => node = child,
+                None => {
+                    self.nodes[node].children.insert(distance, new_node);
+                    return;
+                }
+            }
+        }
+    }
+
+    // Items within `max_distance` of `hash`, with their distance
+    fn find(&self, hash: &PageHash, max_distance: u32) -> Vec<(usize, u32)> {
+        let mut found = Vec::new();
+        let mut pending = if self.nodes.is_empty() { Vec::new() } else { vec![0] };
+        while let Some(node) = pending.pop() {
+            let node = &self.nodes[node];
+            let distance = node.hash.distance(hash);
+            if distance <= max_distance {
+                found.push((node.item, distance));
+            }
+            let range = distance.saturating_sub(max_distance)..=distance + max_distance;
+            pending.extend(
+                node.children
+                    .iter()
+                    .filter(|(child_distance, _)| range.contains(child_distance))
+                    .map(|(_, &child)| child),
+            );
+        }
+        found
+    }
+}
+
+// A rendered page listed in one of the manifests under the output directory
+struct PageRecord {
+    // Index into the loaded manifests
+    document: usize,
+    page: usize,
+    // Relative to the output directory, which is also how reports name pages
+    relative: String,
+    hash: Option<PageHash>,
+}
+
+#[derive(Serialize)]
+struct DuplicateEntry {
+    file: String,
+    distance: u32,
+}
+
+#[derive(Serialize)]
+struct DuplicateGroup {
+    kept: String,
+    duplicates: Vec<DuplicateEntry>,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    generated_at_unix: u64,
+    max_distance: u32,
+    action: &'a str,
+    groups: Vec<DuplicateGroup>,
+}
+
+pub struct DedupeSummary {
+    pub pages: usize,
+    pub groups: usize,
+    pub duplicates: usize,
+}
+
+// Whether two paths are hard links to the same file
+#[cfg(unix)]
+fn same_file(a: &Path, b: &Path) -> Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let (a, b) = (std::fs::metadata(a)?, std::fs::metadata(b)?);
+    Ok(a.dev() == b.dev() && a.ino() == b.ino())
+}
+
+// Without inode numbers we can't tell, and linking again does no harm
+#[cfg(not(unix))]
+fn same_file(_a: &Path, _b: &Path) -> Result<bool> {
+    Ok(false)
+}
+
+// Replace `duplicate` with a hard link to `kept`, unless it already is one
+fn hard_link(kept: &Path, duplicate: &Path) -> Result<()> {
+    if same_file(kept, duplicate)? {
+        return Ok(());
+    }
+
+    // Link beside the duplicate and rename over it, so the page never goes missing
+    let temp_path = duplicate.with_file_name(format!("{}.link", duplicate.file_name().unwrap().to_string_lossy()));
+    std::fs::remove_file(&temp_path).ok();
+    std::fs::hard_link(kept, &temp_path)
+        .with_context(|| format!("Failed to link {} to {}", temp_path.display(), kept.display()))?;
+    std::fs::rename(&temp_path, duplicate)
+        .with_context(|| format!("Failed to replace {}", duplicate.display()))
+}
+
+// Hash every page listed in the manifests under `output_dir`, group pages whose
+// hashes are within `max_distance` bits of the first page of the group (by
+// path), write duplicates.json and hard-link or remove every page of a group but
+// that first one. Hashes are cached in the manifests, so later passes only hash
+// new pages.
+// Group the pages whose hashes are within `max_distance` of each other. Going
+// through the pages in order, each page not yet in a group is kept and takes
+// every other ungrouped page within `max_distance` of it. Groups are built
+// around the kept page rather than chaining near pairs, so a page is never
+// linked or removed for a page it doesn't resemble. Returns each kept page
+// with its group, in which it sorts first.
+fn group_near_pages(hashes: &[PageHash], max_distance: u32) -> BTreeMap<usize, Vec<usize>> {
+    // Identical hashes share a tree node; the tree finds the near misses
+    let mut distinct: HashMap<PageHash, Vec<usize>> = HashMap::new();
+    for (index, hash) in hashes.iter().enumerate() {
+        distinct.entry(*hash).or_default().push(index);
+    }
+    let mut tree = BkTree::new();
+    for (hash, same) in &distinct {
+        tree.insert(*hash, same[0]);
+    }
+
+    let mut members: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    let mut grouped = vec![false; hashes.len()];
+    for kept in 0..hashes.len() {
+        if grouped[kept] {
+            continue;
+        }
+        let mut group = Vec::new();
+        for (near, _) in tree.find(&hashes[kept], max_distance) {
+            for &index in &distinct[&hashes[near]] {
+                if !grouped[index] {
+                    grouped[index] = true;
+                    group.push(index);
+                }
+            }
+        }
+        // Every earlier page is already grouped, so the kept page sorts first
+        group.sort_unstable();
+        members.insert(kept, group);
+    }
+    members
+}
+
+pub fn deduplicate(output_dir: &Path, max_distance: u32, action: DedupeAction, generated_at_unix: u64) -> Result<DedupeSummary> {
+    let mut manifests = manifest::load_manifests(output_dir);
+
+    let mut records: Vec<PageRecord> = manifests
+        .iter()
+        .enumerate()
+        .flat_map(|(document, loaded)| {
+            loaded.manifest.pages.iter().map(move |entry| PageRecord {
+                document,
+                page: entry.page,
+                relative: manifest::relative_name(output_dir, &loaded.dir.join(&entry.file)),
+                hash: entry.phash.as_deref().and_then(PageHash::from_hex),
+            })
+        })
+        .collect();
+
+    // Hash the pages we haven't seen before; pages we can't decode ourselves
+    // (JBIG2, JPEG 2000) are left out
+    records.par_iter_mut().filter(|record| record.hash.is_none()).for_each(|record| {
+        record.hash = PageHash::of_image(&output_dir.join(&record.relative)).ok();
+    });
+    records.retain(|record| record.hash.is_some());
+    records.sort_by(|a, b| a.relative.cmp(&b.relative));
+
+    for record in &records {
+        let loaded = &mut manifests[record.document];
+        let hash = record.hash.map(PageHash::to_hex);
+        if let Some(entry) = loaded.manifest.pages.iter_mut().find(|entry| entry.page == record.page) {
+            if entry.phash != hash {
+                entry.phash = hash;
+                loaded.changed = true;
+            }
+        }
+    }
+
+    let hashes: Vec<PageHash> = records.iter().map(|record| record.hash.unwrap()).collect();
+    let members = group_near_pages(&hashes, max_distance);
+
+    let mut report_groups: BTreeMap<String, Vec<DuplicateEntry>> = BTreeMap::new();
+    let mut duplicates = 0;
+    for (&kept, group) in members.iter().filter(|(_, group)| group.len() > 1) {
+        let kept_record = &records[kept];
+        let kept_path = output_dir.join(&kept_record.relative);
+        let kept_hash = kept_record.hash.unwrap();
+
+        for &index in &group[1..] {
+            let record = &records[index];
+            let path = output_dir.join(&record.relative);
+            let distance = kept_hash.distance(&record.hash.unwrap());
+            // Whether the page counts towards the pages hard-linked or removed
+            let handled = match action {
+                DedupeAction::Report => true,
+                // A link would leave the kept page's bytes under the other
+                // format's extension, so pages in different formats are only
+                // reported
+                DedupeAction::Hardlink if kept_path.extension() != path.extension() => false,
+                DedupeAction::Hardlink => {
+                    hard_link(&kept_path, &path)?;
+                    let loaded = &mut manifests[record.document];
+                    if let Some(entry) = loaded.manifest.pages.iter_mut().find(|entry| entry.page == record.page) {
+                        entry.phash = Some(kept_hash.to_hex());
+                        loaded.changed = true;
+                    }
+                    true
+                }
+                DedupeAction::Remove => {
+                    std::fs::remove_file(&path)
+                        .with_context(|| format!("Failed to remove duplicate page {}", path.display()))?;
+                    let loaded = &mut manifests[record.document];
+                    loaded.manifest.pages.retain(|entry| entry.page != record.page);
+                    loaded.manifest.duplicate_pages.push(DuplicatePage {
+                        page: record.page,
+                        file: record.relative.clone(),
+                        duplicate_of: kept_record.relative.clone(),
+                        distance,
+                    });
+                    loaded.changed = true;
+                    true
+                }
+            };
+            if handled {
+                duplicates += 1;
+            }
+            if action != DedupeAction::Remove {
+                report_groups
+                    .entry(kept_record.relative.clone())
+                    .or_default()
+                    .push(DuplicateEntry { file: record.relative.clone(), distance });
+            }
+        }
+    }
+
+    // Removed pages (by this pass or earlier ones) are only listed in the manifests now
+    for duplicate in manifests.iter().flat_map(|loaded| &loaded.manifest.duplicate_pages) {
+        report_groups
+            .entry(duplicate.duplicate_of.clone())
+            .or_default()
+            .push(DuplicateEntry { file: duplicate.file.clone(), distance: duplicate.distance });
+    }
+
+    for loaded in manifests.iter().filter(|loaded| loaded.changed) {
+        manifest::write_manifest(&loaded.dir, &loaded.pdf_name, &loaded.manifest)?;
+    }
+
+    let summary = DedupeSummary { pages: records.len(), groups: report_groups.len(), duplicates };
+    let report = Report {
+        generated_at_unix,
+        max_distance,
+        action: action.name(),
+        groups: report_groups
+            .into_iter()
+            .map(|(kept, duplicates)| DuplicateGroup { kept, duplicates })
+            .collect(),
+    };
+    let report_path = output_dir.join(REPORT_FILE);
+    let json = serde_json::to_string_pretty(&report).context("Failed to serialize duplicates report")?;
+    std::fs::write(&report_path, json)
+        .with_context(|| format!("Failed to write {}", report_path.display()))?;
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+
+    // A page of horizontal bars whose grey level depends on `seed`
+    fn page(seed: u32) -> GrayImage {
+        GrayImage::from_fn(170, 160, |x, y| {
+            let bar = (x / 10 + y / 10 * 3 + seed) % 7;
+            Luma([(bar * 36) as u8])
+        })
+    }
+
+    fn hash(image: GrayImage) -> PageHash {
+        PageHash::of_pixels(&DynamicImage::ImageLuma8(image))
+    }
+
+    // `hash` with its first `bits` bits flipped
+    fn flipped(hash: PageHash, bits: u32) -> PageHash {
+        let mut words = hash.0;
+        for bit in 0..bits as usize {
+            words[bit / 64] ^= 1 << (bit % 64);
+        }
+        PageHash(words)
+    }
+
+    #[test]
+    fn similar_pages_hash_close_and_different_pages_far() {
+        let original = page(0);
+        let mut speckled = original.clone();
+        speckled.put_pixel(5, 5, Luma([255]));
+        assert_eq!(hash(original.clone()).distance(&hash(original.clone())), 0);
+        assert!(hash(original.clone()).distance(&hash(speckled)) <= 4);
+        assert!(hash(original).distance(&hash(page(3))) > 40);
+    }
+
+    #[test]
+    fn hash_survives_hex_round_trip() {
+        let hash = hash(page(1));
+        assert_eq!(PageHash::from_hex(&hash.to_hex()), Some(hash));
+        assert_eq!(PageHash::from_hex("abc"), None);
+    }
+
+    #[test]
+    fn bk_tree_finds_hashes_within_the_limit() {
+        let base = hash(page(0));
+        let mut tree = BkTree::new();
+        tree.insert(base, 0);
+        tree.insert(flipped(base, 1), 1);
+        tree.insert(flipped(base, 9), 2);
+        tree.insert(hash(page(3)), 3);
+
+        let mut exact = tree.find(&base, 0);
+        exact.sort_unstable();
+        assert_eq!(exact, vec![(0, 0)]);
+
+        let mut near = tree.find(&base, 1);
+        near.sort_unstable();
+        assert_eq!(near, vec![(0, 0), (1, 1)]);
+
+        // The page 9 bits away is above a limit of 8
+        let mut within = tree.find(&base, 8);
+        within.sort_unstable();
+        assert_eq!(within, vec![(0, 0), (1, 1)]);
+    }
+
+    #[test]
+    fn kept_page_sorts_first_in_its_group() {
+        let base = hash(page(0));
+        let other = hash(page(3));
+        let groups = group_near_pages(&[other, base, flipped(base, 2), base], 4);
+        assert_eq!(groups.get(&0), Some(&vec![0]));
+        assert_eq!(groups.get(&1), Some(&vec![1, 2, 3]));
+        assert_eq!(groups.len(), 2);
+    }
+
+    #[test]
+    fn groups_do_not_chain_through_near_pages() {
+        // b is near a and c is near b, but c is too far from a to join its group
+        let a = hash(page(0));
+        let b = flipped(a, 4);
+        let c = flipped(a, 8);
+        let groups = group_near_pages(&[a, b, c], 5);
+        assert_eq!(groups.get(&0), Some(&vec![0, 1]));
+        assert_eq!(groups.get(&2), Some(&vec![2]));
+    }
+}
@@ -0,0 +1,382 @@
+use anyhow::{Context, Result};
+use lopdf::content::Content;
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+use std::path::Path;
+
+use crate::pages;
+use crate::passwords::{self, PdfPassword};
+
+pub struct EmbeddedExtraction {
+    pub page_count: usize,
+    // Pages written straight from their original image stream
+    pub extracted: usize,
+    // Pages that need rasterizing: vector content, several images, an image
+    // encoding we can't store losslessly, or a rotated or flipped scan
+    pub fallback_pages: Vec<usize>,
+    // Pages copied into a container the image crate can't decode, which
+    // generate-video and the page checks pass over
+    pub undecodable_pages: Vec<(usize, &'static str)>,
+}
+
+// Containers we write that the image crate can't decode: JPEG 2000, JBIG2 and
+// CCITT fax wrapped in TIFF
+const UNDECODABLE_EXTENSIONS: &[&str] = &["jp2", "jb2", "tif"];
+
+// Pull the scanned image out of every page that is nothing but a single image,
+// writing it as "{pdf_name}_pageNNN.{ext}" at its native resolution. With
+// `decodable_only` the pages whose container the image crate can't read are
+// left to the rasterizer instead.
+pub fn extract_embedded_images(
+    pdf_path: &Path,
+    output_dir: &Path,
+    pdf_name: &str,
+    password: Option<&PdfPassword>,
+    decodable_only: bool,
+) -> Result<EmbeddedExtraction> {
+    let doc = passwords::load_document(pdf_path, password)?;
+
+    let page_ids = doc.get_pages();
+    let page_count = page_ids.len();
+    let width = pages::page_number_width(page_count);
+
+    let mut extracted = 0;
+    let mut fallback_pages = Vec::new();
+    let mut undecodable_pages = Vec::new();
+
+    for (&page_number, &page_id) in &page_ids {
+        let page = page_number as usize;
+        let image = match sole_page_image(&doc, page_id) {
+            Some(image) => image,
+            None => {
+                fallback_pages.push(page);
+                continue;
+            }
+        };
+
+        match encode_image(&doc, image)? {
+            Some((extension, _)) if decodable_only && UNDECODABLE_EXTENSIONS.contains(&extension) => {
+                fallback_pages.push(page);
+            }
+            Some((extension, bytes)) => {
+                if UNDECODABLE_EXTENSIONS.contains(&extension) {
+                    undecodable_pages.push((page, extension));
+                }
+                let path = output_dir.join(pages::canonical_page_name(pdf_name, page, width, extension));
+                std::fs::write(&path, bytes)
+                    .with_context(|| format!("Failed to write {}", path.display()))?;
+                extracted += 1;
+            }
+            None => fallback_pages.push(page),
+        }
+    }
+
+    Ok(EmbeddedExtraction {
+        page_count,
+        extracted,
+        fallback_pages,
+        undecodable_pages,
+    })
+}
+
+// The page's /Rotate in degrees, which pages can inherit from the page tree
+fn page_rotation(doc: &Document, page_id: ObjectId) -> i64 {
+    let mut dict = doc.get_dictionary(page_id).ok();
+    while let Some(page) = dict {
+        if let Ok(rotate) = page.get(b"Rotate").and_then(Object::as_i64) {
+            return rotate.rem_euclid(360);
+        }
+        dict = page
+            .get(b"Parent")
+            .and_then(Object::as_reference)
+            .and_then(|parent| doc.get_dictionary(parent))
+            .ok();
+    }
+    0
+}
+
+// Multiply the linear part [a b c d] of a cm matrix onto the current one
+fn concat(matrix: [f32; 4], current: [f32; 4]) -> [f32; 4] {
+    let [a, b, c, d] = matrix;
+    let [ca, cb, cc, cd] = current;
+    [a * ca + b * cc, a * cb + b * cd, c * ca + d * cc, c * cb + d * cd]
+}
+
+// Return the image XObject a page consists of, or None if the page paints
+// anything else. Invisible text (render mode 3, as left by OCR) is allowed
+// since it doesn't change what the page looks like. Pages that display the
+// image rotated or flipped, through /Rotate or the image's matrix, are left to
+// the rasterizer, which renders them upright.
+fn sole_page_image(doc: &Document, page_id: ObjectId) -> Option<&Stream> {
+    if page_rotation(doc, page_id) != 0 {
+        return None;
+    }
+    let content = Content::decode(&doc.get_page_content(page_id).ok()?).ok()?;
+    let xobjects = page_xobjects(doc, page_id)?;
+
+    let mut image = None;
+    let mut text_render_mode = 0;
+    // Linear part of the current transformation matrix, and the saved ones
+    let mut matrix = [1.0, 0.0, 0.0, 1.0];
+    let mut saved = Vec::new();
+
+    for operation in &content.operations {
+        match operation.operator.as_str() {
+            "q" => saved.push(matrix),
+            "Q" => matrix = saved.pop().unwrap_or(matrix),
+            "cm" => {
+                let operands: Vec<f32> = operation.operands.iter().filter_map(|operand| operand.as_float().ok()).collect();
+                if operands.len() != 6 {
+                    return None;
+                }
+                matrix = concat([operands[0], operands[1], operands[2], operands[3]], matrix);
+            }
+            // Graphics state, colour and clipping paths have no visible effect on their own
+            "gs" | "w" | "J" | "j" | "M" | "d" | "ri" | "i" => {}
+            "g" | "G" | "rg" | "RG" | "k" | "K" | "cs" | "CS" | "sc" | "SC" | "scn" | "SCN" => {}
+            "m" | "l" | "c" | "v" | "y" | "h" | "re" | "W" | "W*" | "n" => {}
+            "BT" | "ET" | "Tf" | "Td" | "TD" | "Tm" | "T*" | "Tc" | "Tw" | "Tz" | "TL" | "Ts" => {}
+            "Tr" => {
+                text_render_mode = operation.operands.first()?.as_i64().ok()?;
+            }
+            "Tj" | "TJ" | "'" | "\"" => {
+                if text_render_mode != 3 {
+                    return None;
+                }
+            }
+            "Do" => {
+                let name = operation.operands.first()?.as_name().ok()?;
+                let stream = doc
+                    .dereference(xobjects.get(name).ok()?)
+                    .ok()?
+                    .1
+                    .as_stream()
+                    .ok()?;
+                let is_image = stream.dict.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Image".as_slice());
+                if !is_image || image.is_some() {
+                    return None;
+                }
+                // Upright means the image's x axis runs right and its y axis up
+                let [a, b, c, d] = matrix;
+                if b != 0.0 || c != 0.0 || a <= 0.0 || d <= 0.0 {
+                    return None;
+                }
+                image = Some(stream);
+            }
+            // Anything else paints: path fills and strokes, shadings, inline images
+            _ => return None,
+        }
+    }
+
+    image
+}
+
+fn page_xobjects(doc: &Document, page_id: ObjectId) -> Option<&Dictionary> {
+    let (direct, inherited) = doc.get_page_resources(page_id).ok()?;
+
+    let resources = direct
+        .into_iter()
+        .chain(inherited.into_iter().filter_map(|id| doc.get_dictionary(id).ok()));
+
+    for resources in resources {
+        if let Ok(xobjects) = resources.get(b"XObject") {
+            return doc.dereference(xobjects).ok()?.1.as_dict().ok();
+        }
+    }
+
+    None
+}
+
+fn dict_i64(dict: &Dictionary, key: &[u8], default: i64) -> i64 {
+    dict.get(key).and_then(Object::as_i64).unwrap_or(default)
+}
+
+// Turn an image XObject into file contents, keeping the original encoding where
+// a standard container exists for it. None means the caller should rasterize.
+fn encode_image(doc: &Document, image: &Stream) -> Result<Option<(&'static str, Vec<u8>)>> {
+    let dict = &image.dict;
+
+    // Masks, decode arrays and stencils change how the samples map to pixels
+    if dict.has(b"SMask") || dict.has(b"Mask") || dict.has(b"Decode") || dict.has(b"ImageMask") {
+        return Ok(None);
+    }
+
+    let width = dict_i64(dict, b"Width", 0) as u32;
+    let height = dict_i64(dict, b"Height", 0) as u32;
+    if width == 0 || height == 0 {
+        return Ok(None);
+    }
+
+    let filters = image.filters().unwrap_or_default();
+    let params = dict
+        .get(b"DecodeParms")
+        .ok()
+        .and_then(|params| doc.dereference(params).ok())
+        .and_then(|(_, params)| params.as_dict().ok());
+
+    match filters.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["DCTDecode"] => Ok(Some(("jpg", image.content.clone()))),
+        ["JPXDecode"] => Ok(Some(("jp2", image.content.clone()))),
+        ["CCITTFaxDecode"] => Ok(Some(("tif", ccitt_to_tiff(&image.content, width, height, params)))),
+        ["JBIG2Decode"] => {
+            let globals = params
+                .and_then(|params| params.get(b"JBIG2Globals").ok())
+                .and_then(|globals| doc.dereference(globals).ok())
+                .and_then(|(_, globals)| globals.as_stream().ok())
+                .map(|globals| globals.content.as_slice());
+            Ok(Some(("jb2", jbig2_file(&image.content, globals))))
+        }
+        [] | ["FlateDecode"] | ["LZWDecode"] => raw_to_png(doc, image, width, height),
+        _ => Ok(None),
+    }
+}
+
+// Number of colour components for the colour spaces we can write as PNG
+fn color_components(doc: &Document, color_space: &Object) -> Option<usize> {
+    let color_space = doc.dereference(color_space).ok()?.1;
+    match color_space {
+        Object::Name(name) => match name.as_slice() {
+            b"DeviceGray" | b"CalGray" => Some(1),
+            b"DeviceRGB" | b"CalRGB" => Some(3),
+            _ => None,
+        },
+        Object::Array(array) => match array.first()?.as_name().ok()? {
+            b"ICCBased" => {
+                let profile = doc.dereference(array.get(1)?).ok()?.1.as_stream().ok()?;
+                match dict_i64(&profile.dict, b"N", 0) {
+                    1 => Some(1),
+                    3 => Some(3),
+                    _ => None,
+                }
+            }
+            b"CalGray" => Some(1),
+            b"CalRGB" => Some(3),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn raw_to_png(doc: &Document, image: &Stream, width: u32, height: u32) -> Result<Option<(&'static str, Vec<u8>)>> {
+    let components = match image.dict.get(b"ColorSpace").ok().and_then(|cs| color_components(doc, cs)) {
+        Some(components) => components,
+        None => return Ok(None),
+    };
+    let bits = dict_i64(&image.dict, b"BitsPerComponent", 8);
+
+    // lopdf refuses to decompress image streams, so decode a copy without the Subtype
+    let samples = if image.filters().map(|filters| filters.is_empty()).unwrap_or(true) {
+        image.content.clone()
+    } else {
+        let mut stream = image.clone();
+        stream.dict.remove(b"Subtype");
+        match stream.decompressed_content() {
+            Ok(samples) => samples,
+            Err(_) => return Ok(None),
+        }
+    };
+
+    let (w, h) = (width as usize, height as usize);
+    let dynamic = match (components, bits) {
+        (1, 8) if samples.len() >= w * h => {
+            image::GrayImage::from_raw(width, height, samples[..w * h].to_vec()).map(image::DynamicImage::ImageLuma8)
+        }
+        (3, 8) if samples.len() >= w * h * 3 => {
+            image::RgbImage::from_raw(width, height, samples[..w * h * 3].to_vec()).map(image::DynamicImage::ImageRgb8)
+        }
+        (1, 1) => {
+            // Bilevel rows are padded to whole bytes; 0 is black
+            let row_bytes = w.div_ceil(8);
+            if samples.len() < row_bytes * h {
+                return Ok(None);
+            }
+            let mut gray = Vec::with_capacity(w * h);
+            for row in samples.chunks(row_bytes).take(h) {
+                for x in 0..w {
+                    let bit = (row[x / 8] >> (7 - (x % 8))) & 1;
+                    gray.push(if bit == 1 { 255 } else { 0 });
+                }
+            }
+            image::GrayImage::from_raw(width, height, gray).map(image::DynamicImage::ImageLuma8)
+        }
+        _ => None,
+    };
+
+    let dynamic = match dynamic {
+        Some(dynamic) => dynamic,
+        None => return Ok(None),
+    };
+
+    let mut png = Vec::new();
+    dynamic
+        .write_to(&mut png, image::ImageOutputFormat::Png)
+        .context("Failed to encode PNG")?;
+    Ok(Some(("png", png)))
+}
+
+// Wrap a CCITT G3/G4 stream in a minimal single-strip little-endian TIFF
+fn ccitt_to_tiff(data: &[u8], width: u32, height: u32, params: Option<&Dictionary>) -> Vec<u8> {
+    let k = params.map(|params| dict_i64(params, b"K", 0)).unwrap_or(0);
+    let black_is_1 = params
+        .and_then(|params| params.get(b"BlackIs1").and_then(Object::as_bool).ok())
+        .unwrap_or(false);
+    let byte_align = params
+        .and_then(|params| params.get(b"EncodedByteAlign").and_then(Object::as_bool).ok())
+        .unwrap_or(false);
+
+    let compression: u32 = if k < 0 { 4 } else { 3 };
+    let photometric: u32 = if black_is_1 { 1 } else { 0 };
+
+    // (tag, type, value) with type 3 = SHORT, 4 = LONG
+    let mut entries: Vec<(u16, u16, u32)> = vec![
+        (256, 4, width),
+        (257, 4, height),
+        (258, 3, 1),
+        (259, 3, compression),
+        (262, 3, photometric),
+        (273, 4, 0), // strip offset, patched below
+        (277, 3, 1),
+        (278, 4, height),
+        (279, 4, data.len() as u32),
+    ];
+    if compression == 3 {
+        let two_d = if k > 0 { 1 } else { 0 };
+        let fill_bits = if byte_align { 4 } else { 0 };
+        entries.push((292, 4, two_d | fill_bits));
+    }
+
+    let ifd_size = 2 + entries.len() * 12 + 4;
+    let data_offset = (8 + ifd_size) as u32;
+
+    let mut tiff = Vec::with_capacity(data_offset as usize + data.len());
+    tiff.extend_from_slice(b"II");
+    tiff.extend_from_slice(&42u16.to_le_bytes());
+    tiff.extend_from_slice(&8u32.to_le_bytes());
+    tiff.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    for (tag, kind, value) in entries {
+        let value = if tag == 273 { data_offset } else { value };
+        tiff.extend_from_slice(&tag.to_le_bytes());
+        tiff.extend_from_slice(&kind.to_le_bytes());
+        tiff.extend_from_slice(&1u32.to_le_bytes());
+        if kind == 3 {
+            tiff.extend_from_slice(&(value as u16).to_le_bytes());
+            tiff.extend_from_slice(&[0, 0]);
+        } else {
+            tiff.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    tiff.extend_from_slice(&0u32.to_le_bytes());
+    tiff.extend_from_slice(data);
+    tiff
+}
+
+// Embedded JBIG2 streams are bare segments; prepend the standalone file header
+// (sequential organisation, one page) and the shared global segments.
+fn jbig2_file(data: &[u8], globals: Option<&[u8]>) -> Vec<u8> {
+    let mut file = vec![0x97, 0x4A, 0x42, 0x32, 0x0D, 0x0A, 0x1A, 0x0A, 0x01];
+    file.extend_from_slice(&1u32.to_be_bytes());
+    if let Some(globals) = globals {
+        file.extend_from_slice(globals);
+    }
+    file.extend_from_slice(data);
+    file
+}
@@ -0,0 +1,95 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use image::GenericImageView;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Lossless PNG
+    Png,
+    /// JPEG at --quality
+    Jpeg,
+    /// Lossy WebP at --quality
+    Webp,
+    /// TIFF
+    Tiff,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Webp => "webp",
+            OutputFormat::Tiff => "tif",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpeg",
+            OutputFormat::Webp => "webp",
+            OutputFormat::Tiff => "tiff",
+        }
+    }
+
+    // The format of an existing page file, None for ones we can't write
+    // (JPEG 2000, JBIG2 and the other raw streams of embedded mode)
+    pub fn of_file(path: &Path) -> Option<Self> {
+        match path.extension()?.to_string_lossy().to_lowercase().as_str() {
+            "png" => Some(OutputFormat::Png),
+            "jpg" | "jpeg" => Some(OutputFormat::Jpeg),
+            "webp" => Some(OutputFormat::Webp),
+            "tif" | "tiff" => Some(OutputFormat::Tiff),
+            _ => None,
+        }
+    }
+}
+
+// Re-encode a rendered page into the requested format, for backends that can't
+// write it themselves. The source file is removed once the new one is written.
+pub fn transcode(source: &Path, destination: &Path, format: OutputFormat, quality: u8, gray: bool) -> Result<()> {
+    let mut img = image::open(source)
+        .with_context(|| format!("Failed to read {}", source.display()))?;
+    if gray {
+        img = image::DynamicImage::ImageLuma8(img.to_luma8());
+    }
+
+    write_image(&img, destination, format, quality)?;
+    std::fs::remove_file(source)
+        .with_context(|| format!("Failed to remove {}", source.display()))?;
+    Ok(())
+}
+
+// Encode an image into `destination` in the given format
+pub fn write_image(img: &image::DynamicImage, destination: &Path, format: OutputFormat, quality: u8) -> Result<()> {
+    let file = File::create(destination)
+        .with_context(|| format!("Failed to create {}", destination.display()))?;
+    let mut writer = BufWriter::new(file);
+
+    match format {
+        OutputFormat::Png => img.write_to(&mut writer, image::ImageOutputFormat::Png)?,
+        OutputFormat::Jpeg => {
+            let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut writer, quality);
+            encoder.encode_image(img)?;
+        }
+        OutputFormat::Tiff => {
+            let encoder = image::codecs::tiff::TiffEncoder::new(&mut writer);
+            let (width, height) = (img.width(), img.height());
+            match img {
+                image::DynamicImage::ImageLuma8(luma) => encoder.encode(luma, width, height, image::ColorType::L8)?,
+                _ => encoder.encode(&img.to_rgb8(), width, height, image::ColorType::Rgb8)?,
+            }
+        }
+        OutputFormat::Webp => {
+            // WebP has no grayscale mode, so gray pages are stored as RGB
+            let rgb = img.to_rgb8();
+            let encoded = webp::Encoder::from_rgb(&rgb, rgb.width(), rgb.height()).encode(quality as f32);
+            std::io::Write::write_all(&mut writer, &encoded)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,25 @@
+// Union-find over indices, for merging touching runs of pixels into regions
+pub struct Groups {
+    parent: Vec<usize>,
+}
+
+impl Groups {
+    pub fn new(size: usize) -> Self {
+        Self { parent: (0..size).collect() }
+    }
+
+    pub fn root(&mut self, mut item: usize) -> usize {
+        while self.parent[item] != item {
+            self.parent[item] = self.parent[self.parent[item]];
+            item = self.parent[item];
+        }
+        item
+    }
+
+    pub fn join(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.root(a), self.root(b));
+        if a != b {
+            self.parent[a.max(b)] = a.min(b);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputLayout {
+    /// Every page directly in the output directory, named by the PDF's file stem
+    Flat,
+    /// Mirror the archive's folder structure under the output directory
+    Mirror,
+    /// Flat, with a short hash of the PDF's archive path appended to the name
+    Hashed,
+}
+
+// Where a document's pages go and the name they are written under
+pub struct DocumentTarget {
+    pub dir: PathBuf,
+    pub name: String,
+    // Unique per source file, used for scratch space shared across the output root
+    pub key: String,
+}
+
+fn path_hash(relative: &Path) -> String {
+    let digest = Sha256::digest(relative.to_string_lossy().as_bytes());
+    format!("{:x}", digest)[..8].to_string()
+}
+
+pub fn resolve(layout: OutputLayout, archive_dir: &Path, output_dir: &Path, pdf_path: &Path) -> Result<DocumentTarget> {
+    let stem = pdf_path.file_stem()
+        .context("Failed to get PDF file stem")?
+        .to_string_lossy()
+        .to_string();
+    let relative = pdf_path.strip_prefix(archive_dir).unwrap_or(pdf_path);
+    let hash = path_hash(relative);
+    let key = format!("{}-{}", stem, hash);
+
+    let target = match layout {
+        OutputLayout::Flat => DocumentTarget {
+            dir: output_dir.to_path_buf(),
+            name: stem,
+            key,
+        },
+        OutputLayout::Mirror => DocumentTarget {
+            dir: match relative.parent() {
+                Some(parent) => output_dir.join(parent),
+                None => output_dir.to_path_buf(),
+            },
+            name: stem,
+            key,
+        },
+        OutputLayout::Hashed => DocumentTarget {
+            dir: output_dir.to_path_buf(),
+            name: key.clone(),
+            key,
+        },
+    };
+
+    Ok(target)
+}
+
+// Groups of PDFs that would be written under the same name in the flat layout
+pub fn flat_collisions(pdf_files: &[PathBuf]) -> Vec<Vec<PathBuf>> {
+    let mut by_stem: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    for pdf_file in pdf_files {
+        if let Some(stem) = pdf_file.file_stem() {
+            by_stem.entry(stem.to_string_lossy().to_string()).or_default().push(pdf_file.clone());
+        }
+    }
+
+    let mut collisions: Vec<Vec<PathBuf>> = by_stem.into_values().filter(|group| group.len() > 1).collect();
+    collisions.sort();
+    collisions
+}
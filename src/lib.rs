@@ -0,0 +1,3 @@
+// Code shared by the extract and generate-video binaries
+pub mod catalog;
+pub mod decode;
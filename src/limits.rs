@@ -0,0 +1,292 @@
+use anyhow::{Context, Result};
+use std::io::Read;
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+// How often a running tool is checked against its deadline
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Limits applied to an external tool so a malformed PDF can't hang or exhaust
+// the machine. None means unlimited.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProcessLimits {
+    pub timeout: Option<Duration>,
+    pub max_memory: Option<u64>,
+}
+
+// Returned (inside anyhow) when a tool was killed for running past its deadline,
+// so callers can tell timeouts apart from ordinary failures
+#[derive(Debug)]
+pub struct ToolTimeout {
+    pub tool: String,
+    pub timeout: Duration,
+    // Whatever the tool printed before it was killed
+    pub stderr: String,
+}
+
+impl std::fmt::Display for ToolTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} killed after {:.0?} timeout", self.tool, self.timeout)
+    }
+}
+
+impl std::error::Error for ToolTimeout {}
+
+// Returned (inside anyhow) when a tool exits unsuccessfully, keeping its stderr
+// separate so reports can show it verbatim
+#[derive(Debug)]
+pub struct ToolFailure {
+    pub tool: String,
+    pub stderr: String,
+    // Set when the process was killed by a signal while a memory cap was in force
+    pub maybe_out_of_memory: bool,
+}
+
+impl std::fmt::Display for ToolFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} failed", self.tool)?;
+        if self.maybe_out_of_memory {
+            write!(f, " (killed by a signal - possibly over --max-memory)")?;
+        }
+        write!(f, ": {}", self.stderr)
+    }
+}
+
+impl std::error::Error for ToolFailure {}
+
+// The stderr of the tool behind an error, if a tool run caused it
+pub fn tool_stderr(error: &anyhow::Error) -> Option<&str> {
+    if let Some(failure) = error.downcast_ref::<ToolFailure>() {
+        return Some(&failure.stderr);
+    }
+    error.downcast_ref::<ToolTimeout>().map(|timeout| timeout.stderr.as_str())
+}
+
+// Parse a byte size such as "512M", "2G" or "1048576"
+pub fn parse_size(value: &str) -> std::result::Result<u64, String> {
+    let value = value.trim();
+    let (digits, multiplier) = match value.char_indices().last() {
+        Some((index, unit)) if unit.is_ascii_alphabetic() => {
+            let multiplier = match unit.to_ascii_uppercase() {
+                'K' => 1u64 << 10,
+                'M' => 1 << 20,
+                'G' => 1 << 30,
+                'T' => 1 << 40,
+                _ => return Err(format!("unknown size unit '{}' (use K, M, G or T)", unit)),
+            };
+            (&value[..index], multiplier)
+        }
+        _ => (value, 1),
+    };
+
+    let count: u64 = digits
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", value))?;
+    count
+        .checked_mul(multiplier)
+        .filter(|&bytes| bytes > 0)
+        .ok_or_else(|| format!("invalid size '{}'", value))
+}
+
+// Cap the child's address space before it execs
+#[cfg(unix)]
+fn apply_memory_limit(command: &mut Command, max_memory: u64) {
+    use std::os::unix::process::CommandExt;
+
+    let limit = libc::rlimit {
+        rlim_cur: max_memory as libc::rlim_t,
+        rlim_max: max_memory as libc::rlim_t,
+    };
+    // SAFETY: setrlimit is async-signal-safe and touches only the child process
+    unsafe {
+        command.pre_exec(move || {
+            if libc::setrlimit(libc::RLIMIT_AS, &limit) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(unix))]
+fn apply_memory_limit(_command: &mut Command, _max_memory: u64) {}
+
+// Start the tool in its own process group, so a timeout also takes down any
+// helpers it spawned (and closes the stderr pipe they inherited). Only done
+// when a timeout is set: a detached group leaves the terminal's foreground
+// group, so Ctrl-C no longer reaches it and forward_interrupts has to
+#[cfg(unix)]
+fn isolate_process_group(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+    command.process_group(0);
+}
+
+#[cfg(not(unix))]
+fn isolate_process_group(_command: &mut Command) {}
+
+#[cfg(unix)]
+fn kill_tree(child: &mut std::process::Child) {
+    // SAFETY: a child with a deadline leads its own process group (see isolate_process_group)
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_tree(child: &mut std::process::Child) {
+    child.kill().ok();
+}
+
+// Process groups of the detached tools still running, read by the signal
+// handler (so a fixed array of atomics rather than anything that locks)
+#[cfg(unix)]
+static LIVE_GROUPS: [std::sync::atomic::AtomicI32; 256] = [const { std::sync::atomic::AtomicI32::new(0) }; 256];
+
+#[cfg(unix)]
+extern "C" fn kill_live_groups(signal: libc::c_int) {
+    use std::sync::atomic::Ordering;
+
+    for slot in &LIVE_GROUPS {
+        let group = slot.load(Ordering::SeqCst);
+        if group > 0 {
+            // SAFETY: kill is async-signal-safe
+            unsafe {
+                libc::kill(-group, libc::SIGKILL);
+            }
+        }
+    }
+    // Die of the signal as if no handler had been installed
+    // SAFETY: signal and raise are async-signal-safe
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+// Kill the detached tools' process groups on Ctrl-C or SIGTERM, which would
+// otherwise outlive us
+#[cfg(unix)]
+fn forward_interrupts() {
+    static INSTALL: std::sync::Once = std::sync::Once::new();
+    INSTALL.call_once(|| {
+        let handler = kill_live_groups as extern "C" fn(libc::c_int);
+        // SAFETY: the handler only touches atomics and async-signal-safe calls
+        unsafe {
+            libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+            libc::signal(libc::SIGTERM, handler as libc::sighandler_t);
+        }
+    });
+}
+
+// Record a detached child's process group for kill_live_groups, returning its
+// slot (None if every slot is taken, in which case Ctrl-C misses it)
+#[cfg(unix)]
+fn register_group(child: &std::process::Child) -> Option<usize> {
+    use std::sync::atomic::Ordering;
+
+    forward_interrupts();
+    let group = child.id() as i32;
+    LIVE_GROUPS
+        .iter()
+        .position(|slot| slot.compare_exchange(0, group, Ordering::SeqCst, Ordering::SeqCst).is_ok())
+}
+
+#[cfg(not(unix))]
+fn register_group(_child: &std::process::Child) -> Option<usize> {
+    None
+}
+
+#[cfg(unix)]
+fn unregister_group(slot: Option<usize>) {
+    if let Some(slot) = slot {
+        LIVE_GROUPS[slot].store(0, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[cfg(not(unix))]
+fn unregister_group(_slot: Option<usize>) {}
+
+// Wait for the child, killing it once the deadline passes
+fn wait_with_deadline(child: &mut std::process::Child, timeout: Option<Duration>) -> Result<Option<ExitStatus>> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            kill_tree(child);
+            child.wait()?;
+            return Ok(None);
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// Run a tool to completion within `limits`, failing with its stderr if it
+// exits unsuccessfully and with a ToolTimeout if it has to be killed
+pub fn run_limited(command: &mut Command, tool: &str, limits: &ProcessLimits) -> Result<()> {
+    run(command.stdout(Stdio::null()), tool, limits).map(|_| ())
+}
+
+// Like run_limited, for tools that print their results: returns what the tool
+// wrote to stdout
+pub fn run_limited_output(command: &mut Command, tool: &str, limits: &ProcessLimits) -> Result<Vec<u8>> {
+    run(command.stdout(Stdio::piped()), tool, limits)
+}
+
+fn run(command: &mut Command, tool: &str, limits: &ProcessLimits) -> Result<Vec<u8>> {
+    if let Some(max_memory) = limits.max_memory {
+        apply_memory_limit(command, max_memory);
+    }
+
+    if limits.timeout.is_some() {
+        isolate_process_group(command);
+    }
+    let mut child = command
+        .stdin(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to execute {} - is it installed?", tool))?;
+
+    // Drain both pipes on separate threads so a chatty tool can't block on a full pipe
+    let mut stdout_pipe = child.stdout.take();
+    let stdout_reader = std::thread::spawn(move || {
+        let mut stdout = Vec::new();
+        if let Some(pipe) = stdout_pipe.as_mut() {
+            pipe.read_to_end(&mut stdout).ok();
+        }
+        stdout
+    });
+    let mut stderr_pipe = child.stderr.take();
+    let stderr_reader = std::thread::spawn(move || {
+        let mut stderr = String::new();
+        if let Some(pipe) = stderr_pipe.as_mut() {
+            pipe.read_to_string(&mut stderr).ok();
+        }
+        stderr
+    });
+
+    let group = limits.timeout.and_then(|_| register_group(&child));
+    let status = wait_with_deadline(&mut child, limits.timeout);
+    unregister_group(group);
+    let status = status?;
+    let stdout = stdout_reader.join().unwrap_or_default();
+    let stderr = stderr_reader.join().unwrap_or_default();
+
+    match status {
+        None => Err(ToolTimeout {
+            tool: tool.to_string(),
+            timeout: limits.timeout.unwrap_or_default(),
+            stderr: stderr.trim_end().to_string(),
+        }
+        .into()),
+        Some(status) if !status.success() => Err(ToolFailure {
+            tool: tool.to_string(),
+            stderr: stderr.trim_end().to_string(),
+            maybe_out_of_memory: status.code().is_none() && limits.max_memory.is_some(),
+        }
+        .into()),
+        Some(_) => Ok(stdout),
+    }
+}
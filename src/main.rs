@@ -1,13 +1,86 @@
 use anyhow::{Context, Result};
-use clap::Parser;
-use indicatif::{ProgressBar, ProgressStyle};
+use clap::{Parser, ValueEnum};
 use rayon::prelude::*;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use std::process::Command;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use snowdencore::catalog;
 use walkdir::WalkDir;
 
+mod blank;
+mod bundle;
+mod dedupe;
+mod embedded;
+mod format;
+mod groups;
+mod layout;
+mod limits;
+mod manifest;
+mod ocr;
+mod pages;
+mod passwords;
+mod progress;
+mod rasterizer;
+mod redactions;
+mod report;
+mod staging;
+mod straighten;
+mod tags;
+mod text;
+mod watch;
+
+use blank::BlankAction;
+use dedupe::DedupeAction;
+use format::OutputFormat;
+use layout::{DocumentTarget, OutputLayout};
+use ocr::{OcrLayout, OcrOptions, OcrPages};
+use limits::{ProcessLimits, ToolTimeout};
+use passwords::{EncryptedPdf, PasswordMap, PdfPassword};
+use progress::Progress;
+use rasterizer::{Backend, PageRange, Rasterizer, RenderOptions};
+use report::{DocumentStatus, ReportEntry};
+use straighten::StraightenOptions;
+use text::TextExtractor;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ExtractMode {
+    /// Render every page with the selected rasterizer
+    Raster,
+    /// Write scanned page images out as stored in the PDF, rasterizing only pages with other content
+    Embedded,
+}
+
+impl ExtractMode {
+    fn name(&self) -> &'static str {
+        match self {
+            ExtractMode::Raster => "raster",
+            ExtractMode::Embedded => "embedded",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum RetryRung {
+    /// Render again at --retry-dpi
+    LowerDpi,
+    /// Render with each other installed rasterizer
+    OtherBackend,
+    /// Render one page at a time, keeping every page that succeeds
+    PerPage,
+}
+
+impl RetryRung {
+    fn name(&self) -> &'static str {
+        match self {
+            RetryRung::LowerDpi => "lower-dpi",
+            RetryRung::OtherBackend => "other-backend",
+            RetryRung::PerPage => "per-page",
+        }
+    }
+}
+
 #[derive(Parser, Debug)]
 #[command(name = "extract")]
 #[command(about = "Extract PDF pages to PNG images with parallel processing")]
@@ -16,21 +89,246 @@ struct Args {
     #[arg(short, long)]
     workers: Option<usize>,
     
-    /// Skip files that have already been extracted
+    /// Skip files whose extraction is complete and up to date (the default; --force overrides it)
     #[arg(long, default_value = "true")]
     skip_existing: bool,
     
+    /// Re-extract every document, even if its output is complete and up to date
+    #[arg(long)]
+    force: bool,
+    
     /// DPI for image conversion (default: 200)
     #[arg(long, default_value = "200")]
     dpi: u32,
     
-    /// Archive directory (default: "Snowden archive")
-    #[arg(long, default_value = "Snowden archive")]
+    /// Archive directory, or a .zip, .tar or .tar.gz bundle of it (default: "Snowden archive").
+    /// Zip entries must be stored or deflated; encrypted entries and other compression methods are reported as failures
+    #[arg(long, visible_alias = "archive", default_value = "Snowden archive")]
     archive_dir: PathBuf,
     
     /// Output directory (default: "Snowden-PNGs")
     #[arg(long, default_value = "Snowden-PNGs")]
     output_dir: PathBuf,
+    
+    /// PDF rasterizer to use (default: first installed of pdftoppm, mutool, ghostscript)
+    #[arg(long, value_enum, default_value = "auto")]
+    backend: Backend,
+    
+    /// Extraction mode: raster (render pages) or embedded (copy original scans)
+    #[arg(long, value_enum, default_value = "raster")]
+    mode: ExtractMode,
+    
+    /// Also write each page's text layer to a .txt file next to its image
+    #[arg(long)]
+    text: bool,
+    
+    /// Output layout: flat (name by file stem), mirror (recreate archive folders) or hashed (stem plus path hash)
+    #[arg(long, value_enum, default_value = "flat")]
+    layout: OutputLayout,
+    
+    /// Image format for rendered pages
+    #[arg(long, value_enum, default_value = "png")]
+    format: OutputFormat,
+    
+    /// JPEG/WebP quality (1-100)
+    #[arg(long, default_value = "85", value_parser = clap::value_parser!(u8).range(1..=100))]
+    quality: u8,
+    
+    /// Render pages in grayscale
+    #[arg(long)]
+    gray: bool,
+    
+    /// Split documents longer than this into page-range jobs that run on separate workers
+    #[arg(long, default_value = "50", value_parser = clap::value_parser!(u64).range(1..))]
+    pages_per_job: u64,
+    
+    /// Kill the rasterizer if it takes longer than this many seconds per page of a job
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    timeout_per_page: Option<u64>,
+    
+    /// Cap each rasterizer process's memory, e.g. 512M or 2G
+    #[arg(long, value_parser = limits::parse_size)]
+    max_memory: Option<u64>,
+    
+    /// JSON file mapping archive-relative paths or glob patterns to passwords for encrypted PDFs
+    #[arg(long)]
+    passwords: Option<PathBuf>,
+    
+    /// Write a per-document report of this run (CSV if the path ends in .csv, JSON otherwise)
+    #[arg(long)]
+    report: Option<PathBuf>,
+    
+    /// Fallbacks to try, in order, when a document fails to render
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "lower-dpi,other-backend,per-page")]
+    retry: Vec<RetryRung>,
+    
+    /// Don't retry failed documents
+    #[arg(long, conflicts_with = "retry")]
+    no_retry: bool,
+    
+    /// DPI used by the lower-dpi retry
+    #[arg(long, default_value = "150")]
+    retry_dpi: u32,
+    
+    /// Keep running after the initial pass and extract PDFs as they are added to or changed in the archive
+    #[arg(long)]
+    watch: bool,
+    
+    /// Treat pages with less than this fraction of dark pixels as blank, e.g. 0.002 (off by default)
+    #[arg(long, value_parser = blank::parse_threshold)]
+    blank_threshold: Option<f64>,
+    
+    /// What to do with pages under --blank-threshold
+    #[arg(long, value_enum, default_value = "move")]
+    blank_action: BlankAction,
+    
+    /// Straighten tilted pages by detecting the angle of their text lines
+    #[arg(long)]
+    deskew: bool,
+    
+    /// Largest tilt --deskew corrects, in degrees either way
+    #[arg(long, default_value = "5", value_parser = straighten::parse_max_skew)]
+    max_skew: f64,
+    
+    /// Trim uniform borders (white margins, dark scanner edges) off every page
+    #[arg(long)]
+    trim: bool,
+    
+    /// After extracting, group near-duplicate pages across the output and report, hard-link or remove them
+    #[arg(long, value_enum)]
+    dedupe: Option<DedupeAction>,
+    
+    /// Largest perceptual hash difference, in bits out of 256, for two pages to count as duplicates
+    #[arg(long, default_value = "12", value_parser = clap::value_parser!(u32).range(0..=256))]
+    dedupe_distance: u32,
+    
+    /// OCR rendered pages with tesseract: pages without a text layer (the default) or all pages
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "missing")]
+    ocr: Option<OcrPages>,
+    
+    /// Tesseract languages for --ocr, e.g. eng or eng+deu
+    #[arg(long, default_value = "eng")]
+    ocr_lang: String,
+    
+    /// Word bounding box format written next to the OCR text
+    #[arg(long, value_enum, default_value = "hocr")]
+    ocr_layout: OcrLayout,
+    
+    /// After extracting, find solid black redaction boxes on every page and write redactions.json with their coordinates and per-document statistics
+    #[arg(long)]
+    redactions: bool,
+    
+    /// SQLite catalog of documents and pages, which generate-video can select pages from [default: catalog.sqlite in the output directory]
+    #[arg(long)]
+    catalog: Option<PathBuf>,
+    
+    /// Don't record documents and pages in a catalog
+    #[arg(long, conflicts_with = "catalog")]
+    no_catalog: bool,
+    
+    /// Parse classification markings (banners such as TOP SECRET//SI//NOFORN and portion marks) from each page's text layer and OCR text into a .tags.json file next to it
+    #[arg(long)]
+    tags: bool,
+}
+
+// Settings shared by every document in a run
+#[derive(Clone, Copy)]
+struct ExtractOptions<'a> {
+    archive_dir: &'a Path,
+    output_dir: &'a Path,
+    layout: OutputLayout,
+    skip_existing: bool,
+    render: RenderOptions,
+    pages_per_job: usize,
+    timeout_per_page: Option<Duration>,
+    mode: ExtractMode,
+    rasterizer: &'a dyn Rasterizer,
+    text: Option<TextExtractor>,
+    passwords: &'a PasswordMap,
+    // Resolved from `passwords` for the document being extracted
+    password: Option<&'a PdfPassword>,
+    retry: &'a [RetryRung],
+    retry_dpi: u32,
+    blank_threshold: Option<f64>,
+    blank_action: BlankAction,
+    // None unless --deskew or --trim is on
+    straighten: Option<StraightenOptions>,
+    ocr: Option<OcrOptions<'a>>,
+    tags: bool,
+    // Whether a step of this run reads the page images, so embedded mode
+    // mustn't keep pages in containers the image crate can't decode
+    decode_pages: bool,
+    // Installed rasterizers other than `rasterizer`, for the other-backend retry
+    fallback_rasterizers: &'a [Box<dyn Rasterizer>],
+    progress: &'a Progress,
+}
+
+// A tool working through a document of unknown length gets as long as one of
+// this many pages, so broken files that defeat page counting still get time
+// to render without being able to hang a worker for good
+const UNKNOWN_LENGTH_PAGES: usize = 100;
+
+impl ExtractOptions<'_> {
+    // Limits for a tool working through `pages` pages, or a whole document of
+    // unknown length when None
+    fn tool_limits(&self, pages: Option<usize>) -> ProcessLimits {
+        let pages = pages.unwrap_or(UNKNOWN_LENGTH_PAGES).max(1);
+        ProcessLimits {
+            timeout: self.timeout_per_page.map(|timeout| timeout * pages as u32),
+            ..self.render.limits
+        }
+    }
+}
+
+// How a document's pages were finally produced
+struct RenderOutcome {
+    page_count: usize,
+    dpi: u32,
+    backend: &'static str,
+    // The retry rung that succeeded, None when the first attempt did
+    rung: Option<RetryRung>,
+    // Pages the per-page retry couldn't render
+    failed_pages: Vec<usize>,
+    // Pages under --blank-threshold, including those found by earlier runs
+    blank_pages: Vec<manifest::BlankPage>,
+    // Pages an earlier --dedupe remove deleted, carried over by repairs
+    duplicate_pages: Vec<manifest::DuplicatePage>,
+    // How --deskew and --trim changed each page, including pages earlier runs changed
+    transforms: Vec<(usize, manifest::PageTransform)>,
+    // Pages OCR'd, and how many of those came from the cache
+    ocr_pages: usize,
+    ocr_cached: usize,
+    // The classification level of each page --tags found markings on
+    classifications: Vec<String>,
+}
+
+impl RenderOutcome {
+    fn new(page_count: usize, options: &ExtractOptions, rung: Option<RetryRung>) -> Self {
+        Self {
+            page_count,
+            dpi: options.render.dpi,
+            backend: options.rasterizer.name(),
+            rung,
+            failed_pages: Vec::new(),
+            blank_pages: Vec::new(),
+            duplicate_pages: Vec::new(),
+            transforms: Vec::new(),
+            ocr_pages: 0,
+            ocr_cached: 0,
+            classifications: Vec::new(),
+        }
+    }
+    
+    // How the retry succeeded, for logs, reports and the manifest
+    fn retry_description(&self) -> Option<String> {
+        let rung = self.rung?;
+        Some(match rung {
+            RetryRung::LowerDpi => format!("{} ({} DPI)", rung.name(), self.dpi),
+            RetryRung::OtherBackend => format!("{} ({})", rung.name(), self.backend),
+            RetryRung::PerPage if self.failed_pages.is_empty() => rung.name().to_string(),
+            RetryRung::PerPage => format!("{} ({} pages failed)", rung.name(), self.failed_pages.len()),
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -38,7 +336,26 @@ struct ProcessingStats {
     processed: AtomicUsize,
     skipped: AtomicUsize,
     errors: AtomicUsize,
+    repaired: AtomicUsize,
+    // Re-extracted because the source or the settings changed
+    updated: AtomicUsize,
+    timeouts: AtomicUsize,
+    encrypted: AtomicUsize,
     total_pages: AtomicUsize,
+    // Pages under --blank-threshold that were skipped, tagged or moved
+    blank_pages: AtomicUsize,
+    ocr_pages: AtomicUsize,
+    ocr_cached: AtomicUsize,
+    // Pages with classification markings, by level
+    classified_pages: Mutex<HashMap<String, usize>>,
+    // Source bytes of the documents extracted or repaired, for the MB/s rate
+    total_bytes: AtomicU64,
+    repaired_documents: Mutex<Vec<PathBuf>>,
+    timed_out_documents: Mutex<Vec<PathBuf>>,
+    encrypted_documents: Mutex<Vec<PathBuf>>,
+    // Documents that only succeeded on a retry, with the rung that worked
+    retried_documents: Mutex<Vec<(PathBuf, String)>>,
+    report: Mutex<Vec<ReportEntry>>,
 }
 
 impl ProcessingStats {
@@ -47,135 +364,863 @@ impl ProcessingStats {
             processed: AtomicUsize::new(0),
             skipped: AtomicUsize::new(0),
             errors: AtomicUsize::new(0),
+            repaired: AtomicUsize::new(0),
+            updated: AtomicUsize::new(0),
+            timeouts: AtomicUsize::new(0),
+            encrypted: AtomicUsize::new(0),
             total_pages: AtomicUsize::new(0),
+            blank_pages: AtomicUsize::new(0),
+            ocr_pages: AtomicUsize::new(0),
+            ocr_cached: AtomicUsize::new(0),
+            classified_pages: Mutex::new(HashMap::new()),
+            total_bytes: AtomicU64::new(0),
+            repaired_documents: Mutex::new(Vec::new()),
+            timed_out_documents: Mutex::new(Vec::new()),
+            encrypted_documents: Mutex::new(Vec::new()),
+            retried_documents: Mutex::new(Vec::new()),
+            report: Mutex::new(Vec::new()),
         }
     }
+    
+    // Add a document's outcome to the --report entries
+    fn add_report_entry(&self, entry: ReportEntry) {
+        self.report.lock().unwrap().push(entry);
+    }
+    
+    // Report a finished document, noting the retry that rescued it if any
+    fn record_success(
+        &self,
+        pdf_path: &Path,
+        status: DocumentStatus,
+        outcome: &RenderOutcome,
+        started: Instant,
+        source_bytes: u64,
+    ) {
+        let mut entry = ReportEntry::new(pdf_path, status, Some(outcome.page_count), started.elapsed().as_secs_f64(), source_bytes);
+        entry.retry = outcome.retry_description();
+        entry.failed_pages = outcome.failed_pages.clone();
+        if let Some(retry) = &entry.retry {
+            self.retried_documents.lock().unwrap().push((pdf_path.to_path_buf(), retry.clone()));
+        }
+        self.add_report_entry(entry);
+    }
+    
+    // Count a failed document under its category and report it
+    fn record_failure(
+        &self,
+        pdf_path: &Path,
+        error: &anyhow::Error,
+        page_count: Option<usize>,
+        started: Instant,
+        source_bytes: u64,
+        progress: &Progress,
+    ) {
+        let file_name = pdf_path.file_name().unwrap().to_string_lossy();
+        let status = if error.downcast_ref::<ToolTimeout>().is_some() {
+            self.timeouts.fetch_add(1, Ordering::Relaxed);
+            self.timed_out_documents.lock().unwrap().push(pdf_path.to_path_buf());
+            progress.eprintln(format!("  ✗ TIMEOUT processing {}: {}", file_name, error));
+            DocumentStatus::Timeout
+        } else if error.downcast_ref::<EncryptedPdf>().is_some() {
+            self.encrypted.fetch_add(1, Ordering::Relaxed);
+            self.encrypted_documents.lock().unwrap().push(pdf_path.to_path_buf());
+            progress.eprintln(format!("  ✗ ENCRYPTED {}: {}", file_name, error));
+            DocumentStatus::Encrypted
+        } else {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+            progress.eprintln(format!("  ✗ ERROR processing {}: {}", file_name, error));
+            DocumentStatus::Failed
+        };
+        let mut entry = ReportEntry::new(pdf_path, status, page_count, started.elapsed().as_secs_f64(), source_bytes);
+        entry.error = Some(error.to_string());
+        entry.stderr = limits::tool_stderr(error).map(str::to_string);
+        self.add_report_entry(entry);
+    }
+}
+
+// A PDF to extract. `path` is the name it goes by in output names, manifests and
+// reports, `source` the file that is actually read; they only differ for PDFs
+// inside a zip or tar bundle, which are rendered from a temporary copy.
+struct Document {
+    path: PathBuf,
+    source: PathBuf,
+    page_count: Option<usize>,
+    // Deletes the temporary copy of a bundle entry once the document is done
+    copy: Option<bundle::EntryCopy>,
+    // Why a bundle entry couldn't be copied out, reported as its failure
+    unreadable: Option<anyhow::Error>,
 }
 
-fn check_if_extracted(pdf_path: &Path, output_dir: &Path) -> Result<bool> {
-    let pdf_name = pdf_path.file_stem()
-        .context("Failed to get PDF file stem")?
-        .to_string_lossy();
+impl Document {
+    fn on_disk(path: PathBuf, page_count: Option<usize>) -> Self {
+        Self { source: path.clone(), path, page_count, copy: None, unreadable: None }
+    }
+}
+
+// What a previous run left in the output directory for a document
+enum ExistingOutput {
+    None,
+    Complete { page_count: usize },
+    Partial { page_count: usize, missing: Vec<usize> },
+    // Extracted from a different version of the source or with different settings
+    Stale { reason: String },
+}
+
+// Why a previous extraction no longer matches the source file or the settings
+// of this run, if it doesn't
+fn stale_reason(
+    source_path: &Path,
+    manifest: &manifest::Manifest,
+    source: &std::fs::Metadata,
+    options: &ExtractOptions,
+) -> Result<Option<String>> {
+    // Only hash the source when its size or modification time don't settle it
+    if manifest.source_size != source.len() {
+        return Ok(Some("source changed".to_string()));
+    }
+    let mtime_matches = manifest.source_mtime_unix.is_some() && manifest.source_mtime_unix == manifest::modified_unix(source);
+    if !mtime_matches && manifest::sha256_file(source_path)? != manifest.source_sha256 {
+        return Ok(Some("source changed".to_string()));
+    }
     
-    let first_page = output_dir.join(format!("{}_page001.png", pdf_name));
-    Ok(first_page.exists())
+    if manifest.format != options.render.format.name() {
+        return Ok(Some(format!("format changed from {}", manifest.format)));
+    }
+    if manifest.gray != options.render.gray {
+        return Ok(Some("grayscale setting changed".to_string()));
+    }
+    if manifest.mode != options.mode.name() {
+        return Ok(Some(format!("mode changed from {}", manifest.mode)));
+    }
+    if options.text.is_some() && manifest.text_extractor.is_none() {
+        return Ok(Some("text sidecars requested".to_string()));
+    }
+    if let Some(ocr) = &options.ocr {
+        if manifest.ocr_language.as_deref() != Some(ocr.language) || manifest.ocr_layout.as_deref() != Some(ocr.layout.name()) {
+            return Ok(Some("OCR settings changed".to_string()));
+        }
+    }
+    if options.tags && !manifest.classification_tags {
+        return Ok(Some("classification tags requested".to_string()));
+    }
+    let blank_action = options.blank_threshold.map(|_| options.blank_action.name());
+    if manifest.blank_threshold != options.blank_threshold || manifest.blank_action.as_deref() != blank_action {
+        return Ok(Some("blank page settings changed".to_string()));
+    }
+    let (deskew, trim) = match &options.straighten {
+        Some(straighten) => (straighten.deskew, straighten.trim),
+        None => (None, false),
+    };
+    if manifest.deskew != deskew || manifest.trim != trim {
+        return Ok(Some("deskew or trim settings changed".to_string()));
+    }
+    
+    // A document rescued by a retry was deliberately rendered with other
+    // settings; re-extracting it every run would only fail again
+    if manifest.retry.is_none() {
+        if manifest.dpi != options.render.dpi {
+            return Ok(Some(format!("DPI changed from {}", manifest.dpi)));
+        }
+        if manifest.backend != options.rasterizer.name() {
+            return Ok(Some(format!("backend changed from {}", manifest.backend)));
+        }
+    }
+    
+    Ok(None)
 }
 
-fn extract_pdf_to_pngs(
-    pdf_path: &Path,
+fn check_if_extracted(
+    document: &Document,
+    pdf_name: &str,
     output_dir: &Path,
-    skip_existing: bool,
-    dpi: u32,
+    source: &std::fs::Metadata,
+    options: &ExtractOptions,
+) -> Result<ExistingOutput> {
+    let manifest = manifest::read_manifest(output_dir, pdf_name);
+    if manifest.is_none() {
+        // Output from before manifests were written only tells us its format
+        let Some(first_page) = pages::first_page_file(output_dir, pdf_name) else {
+            return Ok(ExistingOutput::None);
+        };
+        let extension = first_page.extension().unwrap_or_default().to_string_lossy();
+        if extension != options.render.format.extension() {
+            return Ok(ExistingOutput::Stale { reason: format!("format changed from {}", extension) });
+        }
+    }
+    
+    // Output written under this name by a different PDF is a collision, not a
+    // previous extraction of this one
+    if let Some(manifest) = &manifest {
+        if manifest.source_path != document.path {
+            return Err(anyhow::anyhow!(
+                "output name {} is already used by {} (try --layout mirror or --layout hashed)",
+                pdf_name,
+                manifest.source_path.display()
+            ));
+        }
+        if let Some(reason) = stale_reason(&document.source, manifest, source, options)? {
+            return Ok(ExistingOutput::Stale { reason });
+        }
+    }
+    
+    // Trust the manifest's page count when we have one, otherwise ask the PDF
+    let page_count = match (&manifest, document.page_count) {
+        (Some(manifest), _) => manifest.page_count,
+        (None, Some(page_count)) => page_count,
+        (None, None) => pages::pdf_page_count(&document.source, options.password, &options.tool_limits(Some(1)))?,
+    };
+    
+    // Blank pages that were skipped or moved away and removed duplicates aren't missing
+    let mut missing = pages::missing_pages(output_dir, pdf_name, page_count);
+    if let Some(manifest) = &manifest {
+        missing.retain(|page| {
+            !manifest.blank_pages.iter().any(|blank| blank.page == *page)
+                && !manifest.duplicate_pages.iter().any(|duplicate| duplicate.page == *page)
+        });
+    }
+    if missing.is_empty() {
+        Ok(ExistingOutput::Complete { page_count })
+    } else {
+        Ok(ExistingOutput::Partial { page_count, missing })
+    }
+}
+
+fn extract_pdf_to_pngs(
+    document: &Document,
+    options: &ExtractOptions,
     stats: Arc<ProcessingStats>,
 ) -> Result<()> {
-    let pdf_name = pdf_path.file_stem()
-        .context("Failed to get PDF file stem")?
-        .to_string_lossy()
-        .to_string();
+    let pdf_path = document.path.as_path();
+    let page_count = document.page_count;
+    let target = layout::resolve(options.layout, options.archive_dir, options.output_dir, pdf_path)?;
+    let pdf_name = &target.name;
+    let options = &ExtractOptions {
+        password: options.passwords.lookup(pdf_path, options.archive_dir),
+        ..*options
+    };
+    let document_options = ExtractOptions { output_dir: &target.dir, ..*options };
+    
+    let started = Instant::now();
+    
+    // Get file size for logging
+    let metadata = std::fs::metadata(&document.source)?;
+    let file_size_mb = metadata.len() as f64 / (1024.0 * 1024.0);
 
-    // Check if already extracted
-    if skip_existing && check_if_extracted(pdf_path, output_dir)? {
+    // Check if already extracted, and whether every page made it
+    let existing = if options.skip_existing {
+        match check_if_extracted(document, pdf_name, &target.dir, &metadata, options) {
+            Ok(existing) => existing,
+            Err(e) => {
+                stats.record_failure(pdf_path, &e, page_count, started, metadata.len(), options.progress);
+                return Ok(());
+            }
+        }
+    } else {
+        ExistingOutput::None
+    };
+    if let ExistingOutput::Complete { page_count } = existing {
         stats.skipped.fetch_add(1, Ordering::Relaxed);
+        stats.add_report_entry(ReportEntry::new(pdf_path, DocumentStatus::Skipped, Some(page_count), 0.0, metadata.len()));
         return Ok(());
     }
-
-    // Get file size for logging
-    let metadata = std::fs::metadata(pdf_path)?;
-    let file_size_mb = metadata.len() as f64 / (1024.0 * 1024.0);
     
-    println!("Processing: {} ({:.2} MB)", pdf_path.file_name().unwrap().to_string_lossy(), file_size_mb);
+    // Show what this worker is doing until the document is finished
+    let status = match &existing {
+        ExistingOutput::Partial { page_count, missing } => format!(
+            "Repairing: {} ({} of {} pages missing)",
+            pdf_path.file_name().unwrap().to_string_lossy(),
+            missing.len(),
+            page_count
+        ),
+        ExistingOutput::Stale { reason } => format!(
+            "Re-extracting: {} ({})",
+            pdf_path.file_name().unwrap().to_string_lossy(),
+            reason
+        ),
+        _ => format!("Processing: {} ({:.2} MB)", pdf_path.file_name().unwrap().to_string_lossy(), file_size_mb),
+    };
+    let _document_line = options.progress.start_document(status)?;
 
-    match extract_pdf_pages_with_pdftoppm(pdf_path, output_dir, &pdf_name, dpi) {
-        Ok(page_count) => {
-            stats.processed.fetch_add(1, Ordering::Relaxed);
-            stats.total_pages.fetch_add(page_count, Ordering::Relaxed);
-            println!("  ✓ Completed: {} ({} pages)", pdf_path.file_name().unwrap().to_string_lossy(), page_count);
+    match extract_staged(document, &target, &existing, options) {
+        Ok(outcome) => {
+            if let Err(e) = write_document_manifest(document, pdf_name, &outcome, &metadata, started, &document_options) {
+                options.progress.eprintln(format!("  ! Failed to write manifest for {}: {}", pdf_path.file_name().unwrap().to_string_lossy(), e));
+            }
+            
+            let mut note = match outcome.retry_description() {
+                Some(retry) => format!(", after retry: {}", retry),
+                None => String::new(),
+            };
+            let new_blank_pages = outcome
+                .blank_pages
+                .iter()
+                .filter(|blank| match &existing {
+                    ExistingOutput::Partial { missing, .. } => missing.contains(&blank.page),
+                    _ => true,
+                })
+                .count();
+            stats.ocr_pages.fetch_add(outcome.ocr_pages, Ordering::Relaxed);
+            stats.ocr_cached.fetch_add(outcome.ocr_cached, Ordering::Relaxed);
+            if outcome.ocr_pages > 0 {
+                note.push_str(&format!(", {} pages OCR'd", outcome.ocr_pages));
+            }
+            if !outcome.classifications.is_empty() {
+                let mut classified_pages = stats.classified_pages.lock().unwrap();
+                for level in &outcome.classifications {
+                    *classified_pages.entry(level.clone()).or_default() += 1;
+                }
+                note.push_str(&format!(", {} pages with classification markings", outcome.classifications.len()));
+            }
+            let straightened = outcome
+                .transforms
+                .iter()
+                .filter(|(page, _)| match &existing {
+                    ExistingOutput::Partial { missing, .. } => missing.contains(page),
+                    _ => true,
+                })
+                .count();
+            if straightened > 0 {
+                note.push_str(&format!(", {} pages straightened", straightened));
+            }
+            if new_blank_pages > 0 {
+                stats.blank_pages.fetch_add(new_blank_pages, Ordering::Relaxed);
+                note.push_str(&format!(", {} blank pages {}", new_blank_pages, options.blank_action.description()));
+            }
+            if let ExistingOutput::Partial { missing, .. } = &existing {
+                let rendered = missing.len() - outcome.failed_pages.len();
+                stats.repaired.fetch_add(1, Ordering::Relaxed);
+                stats.total_pages.fetch_add(rendered, Ordering::Relaxed);
+                stats.total_bytes.fetch_add(metadata.len(), Ordering::Relaxed);
+                stats.repaired_documents.lock().unwrap().push(pdf_path.to_path_buf());
+                stats.record_success(pdf_path, DocumentStatus::Repaired, &outcome, started, metadata.len());
+                options.progress.println(format!("  ✓ Repaired: {} ({} pages re-rendered{})", pdf_path.file_name().unwrap().to_string_lossy(), rendered, note));
+            } else if let ExistingOutput::Stale { reason } = &existing {
+                let rendered = outcome.page_count - outcome.failed_pages.len();
+                stats.updated.fetch_add(1, Ordering::Relaxed);
+                stats.total_pages.fetch_add(rendered, Ordering::Relaxed);
+                stats.total_bytes.fetch_add(metadata.len(), Ordering::Relaxed);
+                stats.record_success(pdf_path, DocumentStatus::Updated, &outcome, started, metadata.len());
+                options.progress.println(format!("  ✓ Re-extracted: {} ({} pages, {}{})", pdf_path.file_name().unwrap().to_string_lossy(), rendered, reason, note));
+            } else {
+                let rendered = outcome.page_count - outcome.failed_pages.len();
+                stats.processed.fetch_add(1, Ordering::Relaxed);
+                stats.total_pages.fetch_add(rendered, Ordering::Relaxed);
+                stats.total_bytes.fetch_add(metadata.len(), Ordering::Relaxed);
+                stats.record_success(pdf_path, DocumentStatus::Processed, &outcome, started, metadata.len());
+                options.progress.println(format!("  ✓ Completed: {} ({} pages{})", pdf_path.file_name().unwrap().to_string_lossy(), rendered, note));
+            }
+            if !outcome.failed_pages.is_empty() {
+                let pages: Vec<String> = outcome.failed_pages.iter().map(|page| page.to_string()).collect();
+                options.progress.eprintln(format!("  ! Could not render pages {} of {}", pages.join(", "), pdf_path.file_name().unwrap().to_string_lossy()));
+            }
         }
+        Err(e) => stats.record_failure(pdf_path, &e, page_count, started, metadata.len(), options.progress),
+    }
+
+    Ok(())
+}
+
+// Render a document (or just its missing pages) into a staging directory and
+// move the results into the output directory only once rendering succeeded, so
+// a crash or rasterizer error never leaves half-written pages next to good
+// output. The per-page retry is the one exception: it publishes the pages it
+// could render and leaves the bad ones missing for a later repair.
+fn extract_staged(
+    document: &Document,
+    target: &DocumentTarget,
+    existing: &ExistingOutput,
+    options: &ExtractOptions,
+) -> Result<RenderOutcome> {
+    let pdf_path = document.source.as_path();
+    let pdf_name = &target.name;
+    let staging = staging::prepare(options.output_dir, &target.key)?;
+    let previous = manifest::read_manifest(&target.dir, pdf_name).filter(|previous| previous.source_path == document.path);
+    
+    let mut outcome = match render_with_retries(pdf_path, target, existing, document.page_count, &staging, options) {
+        Ok(outcome) => outcome,
         Err(e) => {
-            stats.errors.fetch_add(1, Ordering::Relaxed);
-            eprintln!("  ✗ ERROR processing {}: {}", pdf_path.file_name().unwrap().to_string_lossy(), e);
+            staging::discard(&staging);
+            return Err(e);
+        }
+    };
+    let page_count = outcome.page_count;
+    
+    if let Some(threshold) = options.blank_threshold {
+        match blank::handle_blank_pages(&staging, pdf_name, page_count, threshold, options.blank_action) {
+            Ok(blank_pages) => outcome.blank_pages = blank_pages,
+            Err(e) => {
+                staging::discard(&staging);
+                return Err(e);
+            }
+        }
+        // A repair only rendered the missing pages; the blank ones found last
+        // time are still blank
+        if let (ExistingOutput::Partial { .. }, Some(previous)) = (existing, &previous) {
+            outcome.blank_pages.extend(previous.blank_pages.iter().cloned());
+            outcome.blank_pages.sort_by_key(|blank| blank.page);
+        }
+    }
+    if let (ExistingOutput::Partial { .. }, Some(previous)) = (existing, &previous) {
+        outcome.duplicate_pages = previous.duplicate_pages.clone();
+    }
+    
+    // After blank detection, which trimmed margins would throw off, and before
+    // text recognition, which straight pages help
+    if let Some(straighten) = &options.straighten {
+        match straighten::straighten_pages(&staging, pdf_name, page_count, straighten) {
+            Ok(transforms) => outcome.transforms = transforms,
+            Err(e) => {
+                staging::discard(&staging);
+                return Err(e);
+            }
+        }
+        if let (ExistingOutput::Partial { .. }, Some(previous)) = (existing, &previous) {
+            let rendered: Vec<usize> = outcome.transforms.iter().map(|(page, _)| *page).collect();
+            outcome.transforms.extend(
+                previous
+                    .pages
+                    .iter()
+                    .filter(|entry| !rendered.contains(&entry.page))
+                    .filter_map(|entry| Some((entry.page, entry.transform.clone()?))),
+            );
+            outcome.transforms.sort_by_key(|(page, _)| *page);
+        }
+    }
+    
+    if let (Some(extractor), true) = (options.text, page_count > 0) {
+        if let Err(e) = text::write_text_sidecars(extractor, pdf_path, &staging, pdf_name, page_count, options.password, &options.tool_limits(Some(page_count))) {
+            options.progress.eprintln(format!("  ! Failed to extract text from {}: {}", pdf_path.file_name().unwrap().to_string_lossy(), e));
         }
     }
+    
+    if let Some(ocr) = &options.ocr {
+        let pages = ocr_candidates(pdf_path, &staging, pdf_name, page_count, ocr, options);
+        let summary = ocr::ocr_pages(&staging, &options.output_dir.join(ocr::CACHE_DIR), pdf_name, &pages, page_count, ocr);
+        for (page, e) in &summary.failed {
+            options.progress.eprintln(format!("  ! OCR failed for page {} of {}: {}", page, pdf_path.file_name().unwrap().to_string_lossy(), e));
+        }
+        outcome.ocr_pages = summary.pages;
+        outcome.ocr_cached = summary.cached;
+    }
+    
+    if options.tags {
+        match tag_pages(pdf_path, &staging, pdf_name, page_count, options) {
+            Ok(page_tags) => outcome.classifications = page_tags.into_iter().filter_map(|page_tags| page_tags.classification).collect(),
+            Err(e) => options.progress.eprintln(format!("  ! Failed to tag classification markings of {}: {}", pdf_path.file_name().unwrap().to_string_lossy(), e)),
+        }
+    }
+    
+    std::fs::create_dir_all(&target.dir)
+        .with_context(|| format!("Failed to create {}", target.dir.display()))?;
+    
+    // A full re-extraction replaces the previous one; drop the files it listed
+    // that this one didn't write again
+    if !matches!(existing, ExistingOutput::Partial { .. }) {
+        if let Some(previous) = &previous {
+            let keep = staging::file_names(&staging)?;
+            manifest::remove_listed_outputs(&target.dir, previous, &keep);
+        }
+    }
+    
+    staging::publish(&staging, &target.dir)?;
+    Ok(outcome)
+}
 
-    Ok(())
+// The pages rendered into the staging directory that --ocr should recognise.
+// When the text layer can't be read, every page is OCR'd.
+fn ocr_candidates(
+    pdf_path: &Path,
+    staging: &Path,
+    pdf_name: &str,
+    page_count: usize,
+    ocr: &OcrOptions,
+    options: &ExtractOptions,
+) -> Vec<usize> {
+    let rendered = (1..=page_count).filter(|&page| pages::find_page_file(staging, pdf_name, page, page_count).is_some());
+    if ocr.pages == OcrPages::All || page_count == 0 {
+        return rendered.collect();
+    }
+    
+    let extractor = options.text.unwrap_or_else(TextExtractor::detect);
+    let range = PageRange { first: 1, last: page_count };
+    match text::pages_without_text(extractor, pdf_path, range, options.password, &options.tool_limits(Some(page_count))) {
+        Ok(without_text) => rendered.filter(|page| without_text.contains(page)).collect(),
+        Err(e) => {
+            options.progress.eprintln(format!("  ! Failed to read the text layer of {}, OCR-ing every page: {}", pdf_path.file_name().unwrap().to_string_lossy(), e));
+            rendered.collect()
+        }
+    }
 }
 
-fn extract_pdf_pages_with_pdftoppm(
+// Parse the classification markings of the pages rendered into the staging
+// directory, from their text layer and the OCR text written next to them
+fn tag_pages(
     pdf_path: &Path,
-    output_dir: &Path,
+    staging: &Path,
     pdf_name: &str,
-    dpi: u32,
+    page_count: usize,
+    options: &ExtractOptions,
+) -> Result<Vec<tags::PageTags>> {
+    let width = pages::page_number_width(page_count);
+    let mut layer_texts = Vec::new();
+    if page_count > 0 {
+        let extractor = options.text.unwrap_or_else(TextExtractor::detect);
+        let range = PageRange { first: 1, last: page_count };
+        match extractor.page_texts(pdf_path, range, options.password, &options.tool_limits(Some(page_count))) {
+            Ok(texts) => layer_texts = texts,
+            Err(e) => options.progress.eprintln(format!("  ! Failed to read the text layer of {}, tagging from OCR text only: {}", pdf_path.file_name().unwrap().to_string_lossy(), e)),
+        }
+    }
+    
+    let texts: Vec<(usize, Option<String>, Option<String>)> = (1..=page_count)
+        .filter(|&page| pages::find_page_file(staging, pdf_name, page, page_count).is_some())
+        .map(|page| {
+            let ocr_text = std::fs::read_to_string(staging.join(ocr::text_file_name(pdf_name, page, width))).ok();
+            (page, layer_texts.get(page - 1).cloned(), ocr_text)
+        })
+        .collect();
+    tags::write_page_tags(staging, pdf_name, page_count, &texts)
+}
+
+// One rendering attempt into the staging directory
+fn render_attempt(
+    pdf_path: &Path,
+    pdf_name: &str,
+    existing: &ExistingOutput,
+    known_page_count: Option<usize>,
+    options: &ExtractOptions,
 ) -> Result<usize> {
-    // Use pdftoppm (from poppler-utils) to convert PDF to PNG
-    let output_prefix = output_dir.join(format!("{}_page", pdf_name));
+    match existing {
+        ExistingOutput::Partial { page_count, missing } => {
+            repair_missing_pages(pdf_path, pdf_name, *page_count, missing, options)
+        }
+        _ => extract_pdf_pages(pdf_path, pdf_name, known_page_count, options),
+    }
+}
+
+// Render a document, walking down the --retry ladder if the first attempt
+// fails. Each retry starts from an empty staging directory.
+fn render_with_retries(
+    pdf_path: &Path,
+    target: &DocumentTarget,
+    existing: &ExistingOutput,
+    known_page_count: Option<usize>,
+    staging: &Path,
+    options: &ExtractOptions,
+) -> Result<RenderOutcome> {
+    let pdf_name = &target.name;
+    let file_name = pdf_path.file_name().unwrap().to_string_lossy();
+    let staged = ExtractOptions { output_dir: staging, ..*options };
+    
+    let first_error = match render_attempt(pdf_path, pdf_name, existing, known_page_count, &staged) {
+        Ok(page_count) => return Ok(RenderOutcome::new(page_count, &staged, None)),
+        Err(e) => e,
+    };
+    
+    // Rendering differently won't help a document that hangs or needs a password
+    if first_error.downcast_ref::<ToolTimeout>().is_some() || first_error.downcast_ref::<EncryptedPdf>().is_some() {
+        return Err(first_error);
+    }
+    
+    for &rung in options.retry {
+        let attempts: Vec<ExtractOptions> = match rung {
+            RetryRung::LowerDpi if options.retry_dpi < options.render.dpi => vec![ExtractOptions {
+                render: RenderOptions { dpi: options.retry_dpi, ..staged.render },
+                ..staged
+            }],
+            RetryRung::LowerDpi => Vec::new(),
+            RetryRung::OtherBackend => options
+                .fallback_rasterizers
+                .iter()
+                .map(|rasterizer| ExtractOptions { rasterizer: rasterizer.as_ref(), ..staged })
+                .collect(),
+            RetryRung::PerPage => vec![staged],
+        };
+        
+        for attempt in attempts {
+            options.progress.eprintln(format!(
+                "  ! {} failed ({}), retrying: {} with {} at {} DPI",
+                file_name,
+                first_error,
+                rung.name(),
+                attempt.rasterizer.name(),
+                attempt.render.dpi
+            ));
+            staging::prepare(options.output_dir, &target.key)?;
+            
+            let result = match rung {
+                RetryRung::PerPage => render_page_by_page(pdf_path, pdf_name, existing, known_page_count, &attempt)
+                    .map(|(page_count, failed_pages)| RenderOutcome {
+                        failed_pages,
+                        ..RenderOutcome::new(page_count, &attempt, Some(rung))
+                    }),
+                _ => render_attempt(pdf_path, pdf_name, existing, known_page_count, &attempt)
+                    .map(|page_count| RenderOutcome::new(page_count, &attempt, Some(rung))),
+            };
+            match result {
+                Ok(outcome) => return Ok(outcome),
+                Err(e) => options.progress.eprintln(format!("  ! Retry {} of {} failed: {}", rung.name(), file_name, e)),
+            }
+        }
+    }
     
-    let output = Command::new("pdftoppm")
-        .arg("-png")
-        .arg("-r")
-        .arg(dpi.to_string())
-        .arg(pdf_path)
-        .arg(&output_prefix)
-        .output()
-        .context("Failed to execute pdftoppm - is poppler-utils installed?")?;
+    Err(first_error)
+}
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(anyhow::anyhow!("pdftoppm failed: {}", stderr));
+// Last resort: rasterize every page (or every missing page) on its own so one
+// corrupt page can't sink the whole document. Returns the page count and the
+// pages that still failed; errors only if no page could be rendered at all.
+fn render_page_by_page(
+    pdf_path: &Path,
+    pdf_name: &str,
+    existing: &ExistingOutput,
+    known_page_count: Option<usize>,
+    options: &ExtractOptions,
+) -> Result<(usize, Vec<usize>)> {
+    let (page_count, pages) = match existing {
+        ExistingOutput::Partial { page_count, missing } => (*page_count, missing.clone()),
+        _ => {
+            let page_count = match known_page_count {
+                Some(page_count) => page_count,
+                None => pages::pdf_page_count(pdf_path, options.password, &options.tool_limits(Some(1)))?,
+            };
+            (page_count, (1..=page_count).collect())
+        }
+    };
+    
+    let failed_pages: Vec<usize> = pages
+        .par_iter()
+        .copied()
+        .filter(|&page| {
+            let range = PageRange { first: page, last: page };
+            rasterize_pages(pdf_path, pdf_name, Some(range), Some(page_count), options).is_err()
+        })
+        .collect();
+    
+    if failed_pages.len() == pages.len() {
+        return Err(anyhow::anyhow!("no page could be rendered on its own"));
     }
+    Ok((page_count, failed_pages))
+}
 
-    // Count the generated files to determine page count
-    let page_count = count_generated_pages(output_dir, pdf_name)?;
+// Finish an interrupted extraction by rendering only the pages that are missing
+fn repair_missing_pages(
+    pdf_path: &Path,
+    pdf_name: &str,
+    page_count: usize,
+    missing: &[usize],
+    options: &ExtractOptions,
+) -> Result<usize> {
+    render_ranges(pdf_path, pdf_name, &PageRange::runs(missing), page_count, options)?;
     
-    // Rename files to match our naming convention (page001, page002, etc.)
-    rename_generated_files(output_dir, pdf_name, page_count)?;
+    let still_missing = missing
+        .iter()
+        .filter(|&&page| pages::find_page_file(options.output_dir, pdf_name, page, page_count).is_none())
+        .count();
+    if still_missing > 0 {
+        return Err(anyhow::anyhow!("{} pages still missing after repair", still_missing));
+    }
     
     Ok(page_count)
 }
 
-fn count_generated_pages(output_dir: &Path, pdf_name: &str) -> Result<usize> {
-    let mut count = 0;
+fn write_document_manifest(
+    document: &Document,
+    pdf_name: &str,
+    outcome: &RenderOutcome,
+    source: &std::fs::Metadata,
+    started: Instant,
+    options: &ExtractOptions,
+) -> Result<()> {
+    let mut pages = manifest::collect_page_entries(options.output_dir, pdf_name, outcome.page_count);
+    for entry in &mut pages {
+        entry.transform = outcome
+            .transforms
+            .iter()
+            .find(|(page, _)| *page == entry.page)
+            .map(|(_, transform)| transform.clone());
+    }
+    let (deskew, trim) = match &options.straighten {
+        Some(straighten) => (straighten.deskew, straighten.trim),
+        None => (None, false),
+    };
     
-    // pdftoppm generates files with names like "prefix-1.png", "prefix-2.png", etc.
-    for entry in std::fs::read_dir(output_dir)? {
-        let entry = entry?;
-        let file_name = entry.file_name().to_string_lossy().to_string();
-        
-        // Check if this file matches our pattern
-        if file_name.starts_with(&format!("{}_page-", pdf_name)) && file_name.ends_with(".png") {
-            count += 1;
+    let manifest = manifest::Manifest {
+        source_path: document.path.clone(),
+        source_sha256: manifest::sha256_file(&document.source)?,
+        source_size: source.len(),
+        source_mtime_unix: manifest::modified_unix(source),
+        page_count: outcome.page_count,
+        dpi: outcome.dpi,
+        format: options.render.format.name().to_string(),
+        gray: options.render.gray,
+        mode: options.mode.name().to_string(),
+        backend: outcome.backend.to_string(),
+        retry: outcome.retry_description(),
+        text_extractor: options.text.map(|extractor| extractor.name().to_string()),
+        ocr_language: options.ocr.map(|ocr| ocr.language.to_string()),
+        ocr_layout: options.ocr.map(|ocr| ocr.layout.name().to_string()),
+        classification_tags: options.tags,
+        extracted_at_unix: manifest::now_unix(),
+        duration_secs: started.elapsed().as_secs_f64(),
+        pages,
+        blank_threshold: options.blank_threshold,
+        blank_action: options.blank_threshold.map(|_| options.blank_action.name().to_string()),
+        blank_pages: outcome.blank_pages.clone(),
+        deskew,
+        trim,
+        duplicate_pages: outcome.duplicate_pages.clone(),
+    };
+    
+    manifest::write_manifest(options.output_dir, pdf_name, &manifest)
+}
+
+fn extract_pdf_pages(
+    pdf_path: &Path,
+    pdf_name: &str,
+    page_count: Option<usize>,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    match options.mode {
+        ExtractMode::Raster => match page_count {
+            Some(page_count) if page_count > 0 => {
+                let all_pages = PageRange { first: 1, last: page_count };
+                render_ranges(pdf_path, pdf_name, &[all_pages], page_count, options)?;
+                Ok(page_count)
+            }
+            _ => rasterize_pages(pdf_path, pdf_name, None, None, options),
+        },
+        ExtractMode::Embedded => {
+            match embedded::extract_embedded_images(pdf_path, options.output_dir, pdf_name, options.password, options.decode_pages) {
+                Ok(extraction) => {
+                    options.progress.println(format!(
+                        "  {}: {} pages copied from embedded images, {} to rasterize",
+                        pdf_name,
+                        extraction.extracted,
+                        extraction.fallback_pages.len()
+                    ));
+                    if !extraction.undecodable_pages.is_empty() {
+                        let pages: Vec<String> = extraction
+                            .undecodable_pages
+                            .iter()
+                            .map(|(page, extension)| format!("{} (.{})", page, extension))
+                            .collect();
+                        options.progress.eprintln(format!(
+                            "  ! {}: pages {} kept in a container generate-video and the page checks can't decode",
+                            pdf_name,
+                            pages.join(", ")
+                        ));
+                    }
+                    // Render whatever couldn't be copied out directly
+                    let ranges = PageRange::runs(&extraction.fallback_pages);
+                    render_ranges(pdf_path, pdf_name, &ranges, extraction.page_count, options)?;
+                    Ok(extraction.page_count)
+                }
+                Err(e) => {
+                    options.progress.eprintln(format!("  ! Embedded extraction failed for {}, rasterizing instead: {}", pdf_name, e));
+                    rasterize_pages(pdf_path, pdf_name, None, None, options)
+                }
+            }
         }
     }
+}
+
+// Split ranges into jobs of at most --pages-per-job pages and render them in
+// parallel, so a long document spreads across workers that would otherwise idle
+fn render_ranges(
+    pdf_path: &Path,
+    pdf_name: &str,
+    ranges: &[PageRange],
+    page_count: usize,
+    options: &ExtractOptions,
+) -> Result<()> {
+    let jobs: Vec<PageRange> = ranges
+        .iter()
+        .flat_map(|range| range.split(options.pages_per_job))
+        .collect();
     
-    Ok(count)
+    jobs.par_iter().try_for_each(|&range| {
+        rasterize_pages(pdf_path, pdf_name, Some(range), Some(page_count), options).map(|_| ())
+    })
 }
 
-fn rename_generated_files(output_dir: &Path, pdf_name: &str, page_count: usize) -> Result<()> {
-    // pdftoppm generates files like "prefix-1.png", "prefix-2.png"
-    // We want to rename them to "pdfname_page001.png", "pdfname_page002.png"
+// Render pages with the rasterizer and rename them into place. `page_count` sets
+// the page number width; when None it is taken from what was rendered.
+fn rasterize_pages(
+    pdf_path: &Path,
+    pdf_name: &str,
+    range: Option<PageRange>,
+    page_count: Option<usize>,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    // Each job renders into its own scratch directory so concurrent jobs for the
+    // same document never pick up each other's files
+    let output_dir = options.output_dir;
+    let render_dir = match range {
+        Some(range) => output_dir.join(format!(".render-{}-{}", range.first, range.last)),
+        None => output_dir.join(".render"),
+    };
+    std::fs::create_dir_all(&render_dir)
+        .with_context(|| format!("Failed to create {}", render_dir.display()))?;
     
-    for i in 1..=page_count {
-        let old_name = output_dir.join(format!("{}_page-{}.png", pdf_name, i));
-        let new_name = output_dir.join(format!("{}_page{:03}.png", pdf_name, i));
-        
-        if old_name.exists() {
-            std::fs::rename(&old_name, &new_name)
-                .with_context(|| format!("Failed to rename {} to {}", old_name.display(), new_name.display()))?;
-        }
+    let result = render_into(pdf_path, pdf_name, &render_dir, range, page_count, options);
+    std::fs::remove_dir_all(&render_dir).ok();
+    result
+}
+
+fn render_into(
+    pdf_path: &Path,
+    pdf_name: &str,
+    render_dir: &Path,
+    range: Option<PageRange>,
+    page_count: Option<usize>,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    // The timeout scales with the size of the job
+    let job_pages = match (range, page_count) {
+        (Some(range), _) => Some(range.last - range.first + 1),
+        (None, page_count) => page_count,
+    };
+    let render = RenderOptions {
+        limits: options.tool_limits(job_pages),
+        ..options.render
+    };
+    
+    let format = render.format;
+    let native_extension = options.rasterizer.native_extension(format);
+    options.rasterizer.render(pdf_path, render_dir, pdf_name, &render, range, options.password)?;
+
+    // Find what the rasterizer actually wrote
+    let mut generated = pages::discover_generated_pages(render_dir, pdf_name, native_extension)?;
+    if generated.is_empty() {
+        return Err(anyhow::anyhow!("{} produced no pages", options.rasterizer.name()));
     }
     
-    Ok(())
+    // Convert pages the backend couldn't write in the requested format itself
+    if native_extension != format.extension() {
+        generated = generated
+            .into_par_iter()
+            .map(|(page, path)| {
+                let converted = path.with_extension(format.extension());
+                format::transcode(&path, &converted, format, options.render.quality, options.render.gray)?;
+                Ok((page, converted))
+            })
+            .collect::<Result<Vec<_>>>()?;
+    }
+    
+    // Rename to our naming convention
+    let page_count = page_count.unwrap_or_else(|| generated.iter().map(|(page, _)| *page).max().unwrap_or(0));
+    pages::rename_to_canonical(options.output_dir, pdf_name, &generated, page_count, format.extension())?;
+    
+    Ok(page_count)
 }
 
 fn find_pdf_files(archive_dir: &Path) -> Result<Vec<PathBuf>> {
     let mut pdf_files = Vec::new();
     
     for entry in WalkDir::new(archive_dir).into_iter().filter_map(|e| e.ok()) {
-        if entry.file_type().is_file() {
-            if let Some(extension) = entry.path().extension() {
-                if extension.to_string_lossy().to_lowercase() == "pdf" {
-                    pdf_files.push(entry.path().to_path_buf());
-                }
-            }
+        if entry.file_type().is_file() && watch::is_pdf(entry.path()) {
+            pdf_files.push(entry.path().to_path_buf());
         }
     }
     
@@ -205,69 +1250,530 @@ fn main() -> Result<()> {
     // Create output directory
     std::fs::create_dir_all(&args.output_dir)?;
     
-    // Find PDF files
-    let pdf_files = find_pdf_files(&args.archive_dir)?;
+    // Find PDF files, reading the listing of a bundle without unpacking it
+    let is_bundle = bundle::is_bundle(&args.archive_dir);
+    if is_bundle && args.watch {
+        return Err(anyhow::anyhow!("--watch needs an archive directory, not a bundle"));
+    }
+    let pdf_files = if is_bundle {
+        bundle::list_pdfs(&args.archive_dir)?
+    } else {
+        find_pdf_files(&args.archive_dir)?
+    };
     
-    if pdf_files.is_empty() {
+    if pdf_files.is_empty() && !args.watch {
         println!("No PDF files found in {}", args.archive_dir.display());
         return Ok(());
     }
     
     println!("Found {} PDF files", pdf_files.len());
     
-    // Check if pdftoppm is available
-    if Command::new("pdftoppm").arg("-h").output().is_err() {
-        eprintln!("Error: pdftoppm not found. Please install poppler-utils:");
-        eprintln!("  Ubuntu/Debian: sudo apt-get install poppler-utils");
-        eprintln!("  CentOS/RHEL: sudo yum install poppler-utils");
-        eprintln!("  macOS: brew install poppler");
-        std::process::exit(1);
+    if args.layout == OutputLayout::Flat {
+        let collisions = layout::flat_collisions(&pdf_files);
+        if !collisions.is_empty() {
+            eprintln!("Warning: {} file names are shared by several PDFs and will collide in the flat layout:", collisions.len());
+            for group in &collisions {
+                for pdf_file in group {
+                    eprintln!("  {}", pdf_file.display());
+                }
+            }
+            eprintln!("Use --layout mirror or --layout hashed to keep them apart.");
+        }
     }
     
+    // Pick an installed rasterizer
+    let rasterizer = match rasterizer::select_rasterizer(args.backend) {
+        Ok(rasterizer) => rasterizer,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    println!("Backend: {}", rasterizer.name());
+    println!("Format: {}{}", args.format.name(), if args.gray { " (grayscale)" } else { "" });
+    if args.mode == ExtractMode::Embedded {
+        println!("Mode: embedded images (rasterizing pages with other content)");
+    }
+    if args.deskew || args.trim {
+        let mut steps = Vec::new();
+        if args.deskew {
+            steps.push(format!("deskew (up to {} degrees)", args.max_skew));
+        }
+        if args.trim {
+            steps.push("trim borders".to_string());
+        }
+        println!("Straighten: {}", steps.join(", "));
+    }
+    if args.text {
+        println!("Text sidecars: {}", TextExtractor::detect().name());
+    }
+    if let Some(ocr_pages) = args.ocr {
+        if let Err(e) = ocr::check_tesseract(&args.ocr_lang) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        let which = match ocr_pages {
+            OcrPages::Missing => "pages without a text layer",
+            OcrPages::All => "all pages",
+        };
+        println!("OCR: tesseract ({}, {}, {})", args.ocr_lang, args.ocr_layout.name(), which);
+    }
+    if args.tags {
+        println!("Classification tags: from the text layer{}", if args.ocr.is_some() { " and OCR text" } else { "" });
+    }
+    
+    // Installed backends to fall back on when a document won't render
+    let retry: &[RetryRung] = if args.no_retry { &[] } else { &args.retry };
+    let fallback_rasterizers: Vec<Box<dyn Rasterizer>> = rasterizer::all_rasterizers()
+        .into_iter()
+        .filter(|fallback| fallback.name() != rasterizer.name() && fallback.is_available())
+        .collect();
+    if !retry.is_empty() {
+        let rungs: Vec<&str> = retry.iter().map(RetryRung::name).collect();
+        println!("Retry: {}", rungs.join(" -> "));
+    }
+    
+    // Load passwords for encrypted documents
+    let passwords = match &args.passwords {
+        Some(path) => PasswordMap::load(path)?,
+        None => PasswordMap::default(),
+    };
+    if args.passwords.is_some() {
+        println!("Passwords: {} entries", passwords.len());
+    }
+    
+    // Start watching before the initial pass so nothing dropped in while it
+    // runs is missed
+    let watcher = if args.watch {
+        Some(watch::ArchiveWatcher::new(&args.archive_dir)?)
+    } else {
+        None
+    };
+    
     // Setup progress tracking
     let stats = Arc::new(ProcessingStats::new());
-    let progress = ProgressBar::new(pdf_files.len() as u64);
-    progress.set_style(
-        ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})")
-            .context("Failed to set progress bar template")?
-            .progress_chars("#>-"),
-    );
-    
-    // Process files in parallel using rayon
-    pdf_files.par_iter().for_each(|pdf_file| {
-        let result = extract_pdf_to_pngs(
-            pdf_file,
-            &args.output_dir,
-            args.skip_existing,
-            args.dpi,
-            stats.clone(),
-        );
+    let progress = Progress::new()?;
+    
+    let options = ExtractOptions {
+        archive_dir: &args.archive_dir,
+        output_dir: &args.output_dir,
+        layout: args.layout,
+        skip_existing: args.skip_existing && !args.force,
+        pages_per_job: args.pages_per_job as usize,
+        timeout_per_page: args.timeout_per_page.map(Duration::from_secs),
+        render: RenderOptions {
+            dpi: args.dpi,
+            format: args.format,
+            quality: args.quality,
+            gray: args.gray,
+            limits: ProcessLimits {
+                timeout: None,
+                max_memory: args.max_memory,
+            },
+        },
+        mode: args.mode,
+        rasterizer: rasterizer.as_ref(),
+        text: args.text.then(TextExtractor::detect),
+        passwords: &passwords,
+        password: None,
+        retry,
+        retry_dpi: args.retry_dpi,
+        blank_threshold: args.blank_threshold,
+        blank_action: args.blank_action,
+        straighten: (args.deskew || args.trim).then(|| StraightenOptions {
+            deskew: args.deskew.then_some(args.max_skew),
+            trim: args.trim,
+            quality: args.quality,
+        }),
+        ocr: args.ocr.map(|pages| OcrOptions {
+            pages,
+            language: &args.ocr_lang,
+            layout: args.ocr_layout,
+            limits: ProcessLimits {
+                timeout: args.timeout_per_page.map(Duration::from_secs),
+                max_memory: args.max_memory,
+            },
+        }),
+        tags: args.tags,
+        decode_pages: args.blank_threshold.is_some()
+            || args.deskew
+            || args.trim
+            || args.ocr.is_some()
+            || args.redactions
+            || args.dedupe.is_some(),
+        fallback_rasterizers: &fallback_rasterizers,
+        progress: &progress,
+    };
+    
+    if is_bundle {
+        extract_bundle(&args.archive_dir, pdf_files.len(), &options, &stats);
+    } else {
+        extract_batch(&pdf_files, &options, &stats);
+    }
+    write_run_report(&args, &stats);
+    print_summary(&stats, &args.output_dir);
+    run_dedupe(&args);
+    run_redactions(&args);
+    update_catalog(&args, &pdf_files, &stats);
+    
+    if let Some(watcher) = watcher {
+        println!();
+        println!("Watching {} for new PDFs (Ctrl-C to stop)...", args.archive_dir.display());
+        
+        // A PDF that changes after landing no longer matches the hash in its
+        // manifest, so the usual up-to-date check re-extracts it
+        watcher.run(|pdf_files| {
+            progress.println(format!("Detected {} new or modified PDF files", pdf_files.len()));
+            extract_batch(&pdf_files, &options, &stats);
+            write_run_report(&args, &stats);
+            run_dedupe(&args);
+            run_redactions(&args);
+            update_catalog(&args, &pdf_files, &stats);
+        })?;
+    }
+    
+    Ok(())
+}
+
+// Extract a set of PDFs in parallel, largest first
+fn extract_batch(pdf_files: &[PathBuf], options: &ExtractOptions, stats: &Arc<ProcessingStats>) {
+    // Count pages up front so the biggest documents start first and can be
+    // split into page-range jobs, instead of one long PDF finishing last
+    options.progress.println("Counting pages...");
+    let mut documents: Vec<Document> = pdf_files
+        .par_iter()
+        .map(|pdf_file| {
+            let password = options.passwords.lookup(pdf_file, options.archive_dir);
+            Document::on_disk(pdf_file.clone(), pages::pdf_page_count(pdf_file, password, &options.tool_limits(Some(1))).ok())
+        })
+        .collect();
+    documents.sort_by_key(|document| std::cmp::Reverse(document.page_count.unwrap_or(0)));
+    
+    extract_documents(documents.len(), documents.into_iter(), options, stats);
+}
+
+// Extract the PDFs in a zip or tar bundle as they are copied out of it. A
+// tar.gz can only be read front to back, so one thread copies entries out in
+// the order they are stored while the workers render; the channel holds at
+// most one waiting copy per worker.
+fn extract_bundle(bundle: &Path, document_count: usize, options: &ExtractOptions, stats: &Arc<ProcessingStats>) {
+    let (sender, receiver) = mpsc::sync_channel(rayon::current_num_threads());
+    let output_dir = options.output_dir;
+    
+    std::thread::scope(|scope| {
+        let reader = scope.spawn(move || {
+            bundle::for_each_pdf(bundle, output_dir, |entry| sender.send(entry).is_ok())
+        });
+        
+        let documents = receiver.into_iter().map(|entry| match entry {
+            Ok(entry) => Document {
+                path: entry.path,
+                source: entry.file,
+                page_count: None,
+                copy: Some(entry.copy),
+                unreadable: None,
+            },
+            Err(failure) => Document {
+                source: failure.path.clone(),
+                path: failure.path,
+                page_count: None,
+                copy: None,
+                unreadable: Some(failure.error),
+            },
+        });
+        extract_documents(document_count, documents, options, stats);
+        
+        match reader.join() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => eprintln!("Error reading {}: {:#}", bundle.display(), e),
+            Err(_) => eprintln!("Error reading {}: reader thread panicked", bundle.display()),
+        }
+    });
+}
+
+// Extract documents in parallel, in the order the iterator yields them
+fn extract_documents(
+    document_count: usize,
+    documents: impl Iterator<Item = Document> + Send,
+    options: &ExtractOptions,
+    stats: &Arc<ProcessingStats>,
+) {
+    // Throughput is shown for this batch only
+    let pages_before = stats.total_pages.load(Ordering::Relaxed);
+    let bytes_before = stats.total_bytes.load(Ordering::Relaxed);
+    let progress = options.progress;
+    progress.start_batch(document_count as u64);
+    
+    // Process files in parallel using rayon; par_bridge hands documents to
+    // workers in order, so the largest-first ordering is kept
+    documents.par_bridge().for_each(|mut document| {
+        // Bundle entries are counted here rather than by the thread copying them
+        // out, so they are split into page-range jobs and timed like any other.
+        // lopdf counts on the rayon pool, and a reader waiting on the pool while
+        // every worker waits on the reader would deadlock.
+        if document.page_count.is_none() && document.copy.is_some() {
+            let password = options.passwords.lookup(&document.path, options.archive_dir);
+            document.page_count = pages::pdf_page_count(&document.source, password, &options.tool_limits(Some(1))).ok();
+        }
+        
+        let result = match document.unreadable.take() {
+            Some(error) => {
+                stats.record_failure(&document.path, &error, None, Instant::now(), 0, progress);
+                Ok(())
+            }
+            None => extract_pdf_to_pngs(
+                &document,
+                options,
+                stats.clone(),
+            ),
+        };
         
         if let Err(e) = result {
-            eprintln!("Error processing {}: {}", pdf_file.display(), e);
+            progress.eprintln(format!("Error processing {}: {}", document.path.display(), e));
         }
         
-        progress.inc(1);
+        progress.document_done(
+            stats.total_pages.load(Ordering::Relaxed) - pages_before,
+            stats.total_bytes.load(Ordering::Relaxed) - bytes_before,
+        );
     });
     
-    progress.finish_with_message("Complete!");
+    progress.finish();
+    staging::cleanup(options.output_dir);
+}
+
+fn write_run_report(args: &Args, stats: &ProcessingStats) {
+    if let Some(report_path) = &args.report {
+        let generated_at = manifest::now_unix();
+        match report::write_report(report_path, &stats.report.lock().unwrap(), generated_at) {
+            Ok(()) => println!("Report written to {}", report_path.display()),
+            Err(e) => eprintln!("Failed to write report: {}", e),
+        }
+    }
+}
+
+// Group near-duplicate pages across the whole output directory, if --dedupe is on
+fn run_dedupe(args: &Args) {
+    let Some(action) = args.dedupe else {
+        return;
+    };
     
+    println!("Looking for duplicate pages...");
+    let generated_at = manifest::now_unix();
+    match dedupe::deduplicate(&args.output_dir, args.dedupe_distance, action, generated_at) {
+        Ok(summary) => println!(
+            "Duplicates: {} of {} pages {} ({} groups), see {}",
+            summary.duplicates,
+            summary.pages,
+            action.description(),
+            summary.groups,
+            args.output_dir.join(dedupe::REPORT_FILE).display()
+        ),
+        Err(e) => eprintln!("Failed to deduplicate pages: {}", e),
+    }
+}
+
+// Measure redaction boxes across the whole output directory, if --redactions is on
+fn run_redactions(args: &Args) {
+    if !args.redactions {
+        return;
+    }
+    
+    println!("Looking for redactions...");
+    let generated_at = manifest::now_unix();
+    match redactions::analyze(&args.output_dir, generated_at) {
+        Ok(summary) => println!(
+            "Redactions: {} boxes on {} of {} pages, see {}",
+            summary.boxes,
+            summary.redacted_pages,
+            summary.pages,
+            args.output_dir.join(redactions::REPORT_FILE).display()
+        ),
+        Err(e) => eprintln!("Failed to analyze redactions: {}", e),
+    }
+}
+
+// Record the archive's documents, how this run went for each of them, and the
+// pages of every manifest written since the last update in the catalog
+fn update_catalog(args: &Args, pdf_files: &[PathBuf], stats: &ProcessingStats) {
+    if args.no_catalog {
+        return;
+    }
+    
+    let catalog_path = args.catalog.clone().unwrap_or_else(|| args.output_dir.join(catalog::DEFAULT_FILE));
+    let now = manifest::now_unix();
+    match sync_catalog(&catalog_path, &args.output_dir, pdf_files, stats, now) {
+        Ok(updated) => println!("Catalog: {} documents updated in {}", updated, catalog_path.display()),
+        Err(e) => eprintln!("Failed to update catalog {}: {}", catalog_path.display(), e),
+    }
+}
+
+fn sync_catalog(
+    catalog_path: &Path,
+    output_dir: &Path,
+    pdf_files: &[PathBuf],
+    stats: &ProcessingStats,
+    now: u64,
+) -> Result<usize> {
+    let mut catalog = catalog::Catalog::open(catalog_path)?;
+    let source_paths: Vec<String> = pdf_files.iter().map(|path| path.to_string_lossy().to_string()).collect();
+    catalog.record_found(&source_paths, now)?;
+    for entry in stats.report.lock().unwrap().iter() {
+        catalog.record_status(&entry.path.to_string_lossy(), entry.status.name(), entry.error.as_deref(), now)?;
+    }
+    
+    let mut updated = 0;
+    for loaded in manifest::load_manifests(output_dir) {
+        let manifest_path = manifest::manifest_path(&loaded.dir, &loaded.pdf_name);
+        let mtime_ns = std::fs::metadata(&manifest_path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|since_epoch| since_epoch.as_nanos() as i64);
+        let source_path = loaded.manifest.source_path.to_string_lossy();
+        if mtime_ns.is_some() && catalog.manifest_mtime_ns(&source_path)? == mtime_ns {
+            continue;
+        }
+        
+        let document = catalog::DocumentRecord {
+            source_path: &source_path,
+            source_size: loaded.manifest.source_size,
+            source_sha256: &loaded.manifest.source_sha256,
+            page_count: loaded.manifest.page_count,
+            manifest_path: &manifest_path.to_string_lossy(),
+            manifest_mtime_ns: mtime_ns,
+            extracted_at_unix: loaded.manifest.extracted_at_unix,
+        };
+        catalog.record_document(&document, &catalog_pages(&loaded), now)?;
+        updated += 1;
+    }
+    Ok(updated)
+}
+
+// A document's pages as the catalog stores them, from its manifest and the
+// sidecars next to its pages
+fn catalog_pages(loaded: &manifest::LoadedManifest) -> Vec<catalog::PageRecord> {
+    let manifest = &loaded.manifest;
+    let absolute = |file: &str| {
+        let path = loaded.dir.join(file);
+        std::fs::canonicalize(&path).unwrap_or(path).to_string_lossy().to_string()
+    };
+    let read = |file: &Option<String>| {
+        file.as_ref()
+            .and_then(|file| std::fs::read_to_string(loaded.dir.join(file)).ok())
+            .filter(|contents| !contents.trim().is_empty())
+    };
+    
+    let mut pages: Vec<catalog::PageRecord> = manifest
+        .pages
+        .iter()
+        .map(|entry| {
+            let blank = manifest.blank_pages.iter().any(|blank| blank.page == entry.page);
+            let mut page = catalog::PageRecord::new(entry.page, if blank { "blank" } else { "ok" }, Some(absolute(&entry.file)));
+            (page.text, page.text_source) = match (read(&entry.text_file), read(&entry.ocr_text_file)) {
+                (Some(text), _) => (Some(text), Some("text")),
+                (None, Some(text)) => (Some(text), Some("ocr")),
+                (None, None) => (None, None),
+            };
+            page.width = entry.width;
+            page.height = entry.height;
+            page.phash = entry.phash.clone();
+            page.classification = entry.classification.clone();
+            page.tags = read(&entry.tags_file);
+            page.redaction_coverage = entry.redactions.as_ref().map(|redactions| redactions.coverage);
+            page.redaction_boxes = entry.redactions.as_ref().map(|redactions| redactions.boxes.len());
+            page.transform = entry.transform.as_ref().and_then(|transform| serde_json::to_string(transform).ok());
+            page
+        })
+        .collect();
+    
+    // Blank pages that were moved or skipped, and duplicates that were removed
+    for blank in &manifest.blank_pages {
+        if !pages.iter().any(|page| page.page == blank.page) {
+            pages.push(catalog::PageRecord::new(blank.page, "blank", blank.file.as_deref().map(absolute)));
+        }
+    }
+    for duplicate in &manifest.duplicate_pages {
+        pages.push(catalog::PageRecord::new(duplicate.page, "duplicate", None));
+    }
+    pages.sort_by_key(|page| page.page);
+    pages
+}
+
+fn print_summary(stats: &ProcessingStats, output_dir: &Path) {
     // Print final statistics
     let processed = stats.processed.load(Ordering::Relaxed);
     let skipped = stats.skipped.load(Ordering::Relaxed);
     let errors = stats.errors.load(Ordering::Relaxed);
+    let repaired = stats.repaired.load(Ordering::Relaxed);
+    let updated = stats.updated.load(Ordering::Relaxed);
+    let timeouts = stats.timeouts.load(Ordering::Relaxed);
+    let encrypted = stats.encrypted.load(Ordering::Relaxed);
     let total_pages = stats.total_pages.load(Ordering::Relaxed);
+    let blank_pages = stats.blank_pages.load(Ordering::Relaxed);
+    let ocr_pages = stats.ocr_pages.load(Ordering::Relaxed);
+    let ocr_cached = stats.ocr_cached.load(Ordering::Relaxed);
     
     println!();
     println!("{}", "=".repeat(60));
     println!("Complete!");
     println!("Processed: {} files", processed);
     println!("Skipped (already extracted): {} files", skipped);
+    println!("Repaired (partial extractions completed): {} files", repaired);
+    println!("Re-extracted (source or settings changed): {} files", updated);
     println!("Errors: {} files", errors);
-    println!("Total: {} files", processed + skipped + errors);
+    println!("Timed out: {} files", timeouts);
+    println!("Encrypted (password needed): {} files", encrypted);
+    println!("Total: {} files", processed + skipped + repaired + updated + errors + timeouts + encrypted);
     println!("Total pages extracted: {}", total_pages);
-    println!("Output directory: {}", args.output_dir.display());
+    println!("Blank pages (under --blank-threshold): {}", blank_pages);
+    println!("OCR'd pages: {} ({} from cache)", ocr_pages, ocr_cached);
+    let classified_pages = stats.classified_pages.lock().unwrap();
+    let by_level: Vec<String> = tags::levels()
+        .filter_map(|level| classified_pages.get(level).map(|count| format!("{}: {}", level, count)))
+        .collect();
+    let classified_total: usize = classified_pages.values().sum();
+    if by_level.is_empty() {
+        println!("Pages with classification markings: {}", classified_total);
+    } else {
+        println!("Pages with classification markings: {} ({})", classified_total, by_level.join(", "));
+    }
+    println!("Output directory: {}", output_dir.display());
     
-    Ok(())
-}
\ No newline at end of file
+    let repaired_documents = stats.repaired_documents.lock().unwrap();
+    if !repaired_documents.is_empty() {
+        println!();
+        println!("Repaired documents:");
+        for document in repaired_documents.iter() {
+            println!("  {}", document.display());
+        }
+    }
+    
+    let timed_out_documents = stats.timed_out_documents.lock().unwrap();
+    if !timed_out_documents.is_empty() {
+        println!();
+        println!("Timed out documents:");
+        for document in timed_out_documents.iter() {
+            println!("  {}", document.display());
+        }
+    }
+    
+    let retried_documents = stats.retried_documents.lock().unwrap();
+    if !retried_documents.is_empty() {
+        println!();
+        println!("Recovered by retry:");
+        for (document, retry) in retried_documents.iter() {
+            println!("  {} ({})", document.display(), retry);
+        }
+    }
+    
+    let encrypted_documents = stats.encrypted_documents.lock().unwrap();
+    if !encrypted_documents.is_empty() {
+        println!();
+        println!("Encrypted documents (add passwords with --passwords):");
+        for document in encrypted_documents.iter() {
+            println!("  {}", document.display());
+        }
+    }
+    
+}
@@ -0,0 +1,305 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::blank::REJECTED_DIR;
+use crate::ocr::{self, OcrLayout};
+use crate::pages;
+use crate::tags;
+
+// Provenance record written next to a document's pages as "{pdf_name}.manifest.json"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub source_path: PathBuf,
+    pub source_sha256: String,
+    pub source_size: u64,
+    // Lets unchanged sources be recognised without hashing them again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_mtime_unix: Option<u64>,
+    pub page_count: usize,
+    pub dpi: u32,
+    #[serde(default = "default_format")]
+    pub format: String,
+    #[serde(default)]
+    pub gray: bool,
+    pub mode: String,
+    pub backend: String,
+    // The retry rung that rescued the extraction, if the first attempt failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text_extractor: Option<String>,
+    // --ocr-lang and --ocr-layout, when OCR was on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ocr_language: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ocr_layout: Option<String>,
+    // Whether --tags parsed classification markings into page tags
+    #[serde(default)]
+    pub classification_tags: bool,
+    pub extracted_at_unix: u64,
+    pub duration_secs: f64,
+    pub pages: Vec<PageEntry>,
+    // --blank-threshold and --blank-action, when blank detection was on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blank_threshold: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blank_action: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blank_pages: Vec<BlankPage>,
+    // --max-skew when --deskew was on, and --trim
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deskew: Option<f64>,
+    #[serde(default)]
+    pub trim: bool,
+    // Pages deleted by --dedupe remove
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub duplicate_pages: Vec<DuplicatePage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PageEntry {
+    pub page: usize,
+    pub file: String,
+    // None for formats we can't decode ourselves (JBIG2, JPEG 2000, CCITT TIFF)
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text_file: Option<String>,
+    // Tesseract's plain text and word boxes (hOCR or TSV)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ocr_text_file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ocr_layout_file: Option<String>,
+    // The page's classification markings, and the highest level among them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags_file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub classification: Option<String>,
+    // Perceptual hash (hex), cached by --dedupe
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phash: Option<String>,
+    // Redaction boxes found by --redactions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redactions: Option<PageRedactions>,
+    // How --deskew and --trim changed the rendered page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transform: Option<PageTransform>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PageTransform {
+    // Size of the page as rendered
+    pub original_width: u32,
+    pub original_height: u32,
+    // Applied in this order: the part of the rendered page left after trimming
+    // its borders, the counter-clockwise rotation in degrees that straightened
+    // it, and the part of the rotated page left after trimming the corners the
+    // rotation uncovered
+    pub border_crop: Option<PageCrop>,
+    pub rotation_degrees: f64,
+    pub rotated_crop: Option<PageCrop>,
+}
+
+// In pixels from the top left corner of the image it was cut from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageCrop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// The solid black redaction boxes on a page
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PageRedactions {
+    // Fraction of the page the boxes cover, from 0.0 to 1.0
+    pub coverage: f64,
+    pub boxes: Vec<RedactionBox>,
+}
+
+// In pixels of the page image, from its top left corner
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RedactionBox {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// A page whose ink coverage fell below --blank-threshold
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlankPage {
+    pub page: usize,
+    pub ink_coverage: f64,
+    // Relative to the output directory: the page itself when tagged, under
+    // rejected/ when moved, None when skipped
+    pub file: Option<String>,
+}
+
+// A page removed as a near-duplicate of another page in the output
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuplicatePage {
+    pub page: usize,
+    // The removed file and the page that was kept, relative to the output directory
+    pub file: String,
+    pub duplicate_of: String,
+    // Perceptual hash difference in bits
+    pub distance: u32,
+}
+
+fn default_format() -> String {
+    "png".to_string()
+}
+
+pub fn manifest_path(output_dir: &Path, pdf_name: &str) -> PathBuf {
+    output_dir.join(format!("{}.manifest.json", pdf_name))
+}
+
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("Failed to hash {}", path.display()))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+pub fn modified_unix(metadata: &std::fs::Metadata) -> Option<u64> {
+    let modified = metadata.modified().ok()?;
+    modified.duration_since(std::time::UNIX_EPOCH).ok().map(|d| d.as_secs())
+}
+
+// The current time in seconds since the Unix epoch, for the *_unix fields of
+// manifests, reports and the catalog
+pub fn now_unix() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// Describe the pages of a document as they exist on disk
+pub fn collect_page_entries(output_dir: &Path, pdf_name: &str, page_count: usize) -> Vec<PageEntry> {
+    let width = pages::page_number_width(page_count);
+
+    (1..=page_count)
+        .filter_map(|page| {
+            let path = pages::find_page_file(output_dir, pdf_name, page, page_count)?;
+            let (width_px, height_px) = match image::image_dimensions(&path) {
+                Ok((w, h)) => (Some(w), Some(h)),
+                Err(_) => (None, None),
+            };
+            let text_name = pages::canonical_page_name(pdf_name, page, width, "txt");
+            let text_file = output_dir.join(&text_name).exists().then_some(text_name);
+            let ocr_text_name = ocr::text_file_name(pdf_name, page, width);
+            let ocr_text_file = output_dir.join(&ocr_text_name).exists().then_some(ocr_text_name);
+            let ocr_layout_file = [OcrLayout::Hocr, OcrLayout::Tsv]
+                .iter()
+                .map(|layout| ocr::layout_file_name(pdf_name, page, width, layout.name()))
+                .find(|name| output_dir.join(name).exists());
+            let tags_name = tags::tags_file_name(pdf_name, page, width);
+            let page_tags = tags::read_page_tags(&output_dir.join(&tags_name));
+            let classification = page_tags.as_ref().and_then(|page_tags| page_tags.classification.clone());
+            let tags_file = page_tags.map(|_| tags_name);
+
+            Some(PageEntry {
+                page,
+                file: path.file_name()?.to_string_lossy().to_string(),
+                width: width_px,
+                height: height_px,
+                text_file,
+                ocr_text_file,
+                ocr_layout_file,
+                tags_file,
+                classification,
+                phash: None,
+                redactions: None,
+                transform: None,
+            })
+        })
+        .collect()
+}
+
+// Delete the page images, sidecars and rejected blank pages a manifest lists,
+// except those named in `keep`, so a re-extraction doesn't leave stale pages
+// behind (a document that lost pages, or pages written with another extension)
+pub fn remove_listed_outputs(output_dir: &Path, manifest: &Manifest, keep: &HashSet<String>) {
+    let files = manifest
+        .pages
+        .iter()
+        .flat_map(|page| {
+            std::iter::once(&page.file)
+                .chain(page.text_file.as_ref())
+                .chain(page.ocr_text_file.as_ref())
+                .chain(page.ocr_layout_file.as_ref())
+                .chain(page.tags_file.as_ref())
+        })
+        .chain(manifest.blank_pages.iter().filter_map(|blank| blank.file.as_ref()));
+    for file in files {
+        if !keep.contains(file) {
+            let path = output_dir.join(file);
+            std::fs::remove_file(&path).ok();
+            // Drop rejected/ too once its last page is gone
+            if let Some(parent) = path.parent().filter(|parent| *parent != output_dir) {
+                std::fs::remove_dir(parent).ok();
+            }
+        }
+    }
+}
+
+pub fn read_manifest(output_dir: &Path, pdf_name: &str) -> Option<Manifest> {
+    read_manifest_file(&manifest_path(output_dir, pdf_name))
+}
+
+pub fn read_manifest_file(path: &Path) -> Option<Manifest> {
+    let json = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+// The document name a manifest file belongs to ("report" for "report.manifest.json")
+pub fn manifest_document_name(path: &Path) -> Option<&str> {
+    path.file_name()?.to_str()?.strip_suffix(".manifest.json")
+}
+
+// A manifest found under the output directory, written back if `changed`
+pub struct LoadedManifest {
+    pub dir: PathBuf,
+    pub pdf_name: String,
+    pub manifest: Manifest,
+    pub changed: bool,
+}
+
+// Every manifest under the output directory, skipping the staging area and
+// rejected blank pages
+pub fn load_manifests(output_dir: &Path) -> Vec<LoadedManifest> {
+    WalkDir::new(output_dir)
+        .into_iter()
+        .filter_entry(|e| {
+            let name = e.file_name().to_string_lossy();
+            e.depth() == 0 || !(name.starts_with('.') || (e.file_type().is_dir() && name == REJECTED_DIR))
+        })
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| {
+            let pdf_name = manifest_document_name(e.path())?.to_string();
+            let manifest = read_manifest_file(e.path())?;
+            Some(LoadedManifest { dir: e.path().parent()?.to_path_buf(), pdf_name, manifest, changed: false })
+        })
+        .collect()
+}
+
+// A path relative to the output directory, which is how reports name pages
+pub fn relative_name(output_dir: &Path, path: &Path) -> String {
+    path.strip_prefix(output_dir).unwrap_or(path).to_string_lossy().to_string()
+}
+
+pub fn write_manifest(output_dir: &Path, pdf_name: &str, manifest: &Manifest) -> Result<()> {
+    let path = manifest_path(output_dir, pdf_name);
+    let json = serde_json::to_string_pretty(manifest).context("Failed to serialize manifest")?;
+
+    // Write beside the final name and rename, so readers never see a partial manifest
+    let temp_path = path.with_extension("json.tmp");
+    std::fs::write(&temp_path, json).with_context(|| format!("Failed to write {}", temp_path.display()))?;
+    std::fs::rename(&temp_path, &path).with_context(|| format!("Failed to write {}", path.display()))
+}
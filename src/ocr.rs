@@ -0,0 +1,179 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::limits::{self, ProcessLimits};
+use crate::manifest;
+use crate::pages;
+
+// OCR results by page image hash and language, under the output root, so a page
+// is never recognised twice: not when a document is re-extracted, and not when
+// the same page turns up in another document
+pub const CACHE_DIR: &str = ".ocr-cache";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OcrPages {
+    /// Only pages without a text layer (image-only scans)
+    Missing,
+    /// Every page
+    All,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OcrLayout {
+    /// hOCR (HTML with a bounding box for every line and word)
+    Hocr,
+    /// Tab-separated word boxes with confidences
+    Tsv,
+}
+
+impl OcrLayout {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OcrLayout::Hocr => "hocr",
+            OcrLayout::Tsv => "tsv",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct OcrOptions<'a> {
+    pub pages: OcrPages,
+    // Tesseract language codes, e.g. "eng" or "eng+deu"
+    pub language: &'a str,
+    pub layout: OcrLayout,
+    // Applied to every tesseract run, which handles a single page
+    pub limits: ProcessLimits,
+}
+
+// Check tesseract is installed and has the requested languages
+pub fn check_tesseract(language: &str) -> Result<()> {
+    let output = Command::new("tesseract")
+        .arg("--list-langs")
+        .output()
+        .context("Failed to execute tesseract - is it installed?")?;
+    let listing = String::from_utf8_lossy(&output.stdout);
+    let installed: Vec<&str> = listing.lines().skip(1).map(str::trim).collect();
+
+    let missing: Vec<&str> = language.split('+').filter(|lang| !installed.contains(lang)).collect();
+    if !missing.is_empty() {
+        return Err(anyhow::anyhow!(
+            "tesseract has no language data for {} (installed: {})",
+            missing.join(", "),
+            installed.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+// "{pdf_name}_pageNNN.ocr", which tesseract extends with .txt and .hocr/.tsv
+fn output_base(pdf_name: &str, page: usize, width: usize) -> String {
+    pages::canonical_page_name(pdf_name, page, width, "ocr")
+}
+
+pub fn text_file_name(pdf_name: &str, page: usize, width: usize) -> String {
+    format!("{}.txt", output_base(pdf_name, page, width))
+}
+
+pub fn layout_file_name(pdf_name: &str, page: usize, width: usize, layout: &str) -> String {
+    format!("{}.{}", output_base(pdf_name, page, width), layout)
+}
+
+// The cached results for a page image: "{sha256}-{language}.txt" and ".hocr"/".tsv"
+fn cache_paths(cache_dir: &Path, image: &Path, options: &OcrOptions) -> Result<(PathBuf, PathBuf)> {
+    let key = format!("{}-{}", manifest::sha256_file(image)?, options.language);
+    Ok((
+        cache_dir.join(format!("{}.txt", key)),
+        cache_dir.join(format!("{}.{}", key, options.layout.name())),
+    ))
+}
+
+// Copy a file into place through a temporary name, so parallel workers never
+// see half of it
+fn copy_atomic(from: &Path, to: &Path) -> Result<()> {
+    static NEXT_TEMP: AtomicUsize = AtomicUsize::new(0);
+    let temp_path = to.with_file_name(format!(
+        "{}.{}-{}.tmp",
+        to.file_name().unwrap().to_string_lossy(),
+        std::process::id(),
+        NEXT_TEMP.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::copy(from, &temp_path).with_context(|| format!("Failed to copy {} to {}", from.display(), temp_path.display()))?;
+    std::fs::rename(&temp_path, to).with_context(|| format!("Failed to write {}", to.display()))
+}
+
+// OCR one page image into "{base}.txt" and "{base}.{layout}" in `dir`.
+// Returns whether the result came from the cache.
+fn ocr_page(image: &Path, dir: &Path, base: &str, cache_dir: &Path, options: &OcrOptions) -> Result<bool> {
+    let text_path = dir.join(format!("{}.txt", base));
+    let layout_path = dir.join(format!("{}.{}", base, options.layout.name()));
+    let (cached_text, cached_layout) = cache_paths(cache_dir, image, options)?;
+
+    if cached_text.exists() && cached_layout.exists() {
+        std::fs::copy(&cached_text, &text_path)
+            .with_context(|| format!("Failed to copy {}", cached_text.display()))?;
+        std::fs::copy(&cached_layout, &layout_path)
+            .with_context(|| format!("Failed to copy {}", cached_layout.display()))?;
+        return Ok(true);
+    }
+
+    // Workers already run one tesseract per core; its own threads only contend
+    let mut command = Command::new("tesseract");
+    command
+        .env("OMP_THREAD_LIMIT", "1")
+        .arg(image)
+        .arg(dir.join(base))
+        .arg("-l")
+        .arg(options.language)
+        .arg("txt")
+        .arg(options.layout.name());
+    limits::run_limited(&mut command, "tesseract", &options.limits)?;
+
+    if !text_path.exists() || !layout_path.exists() {
+        return Err(anyhow::anyhow!("tesseract produced no output for {}", image.display()));
+    }
+    std::fs::create_dir_all(cache_dir).with_context(|| format!("Failed to create {}", cache_dir.display()))?;
+    copy_atomic(&text_path, &cached_text)?;
+    copy_atomic(&layout_path, &cached_layout)?;
+    Ok(false)
+}
+
+pub struct OcrSummary {
+    pub pages: usize,
+    pub cached: usize,
+    pub failed: Vec<(usize, anyhow::Error)>,
+}
+
+// OCR the given pages of a document in `dir`, in parallel
+pub fn ocr_pages(
+    dir: &Path,
+    cache_dir: &Path,
+    pdf_name: &str,
+    pages: &[usize],
+    page_count: usize,
+    options: &OcrOptions,
+) -> OcrSummary {
+    let width = pages::page_number_width(page_count);
+    let results: Vec<(usize, Result<bool>)> = pages
+        .par_iter()
+        .filter_map(|&page| {
+            let image = pages::find_page_file(dir, pdf_name, page, page_count)?;
+            Some((page, ocr_page(&image, dir, &output_base(pdf_name, page, width), cache_dir, options)))
+        })
+        .collect();
+
+    let mut summary = OcrSummary { pages: 0, cached: 0, failed: Vec::new() };
+    for (page, result) in results {
+        match result {
+            Ok(cached) => {
+                summary.pages += 1;
+                summary.cached += cached as usize;
+            }
+            Err(e) => summary.failed.push((page, e)),
+        }
+    }
+    summary
+}
@@ -0,0 +1,201 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::limits::{self, ProcessLimits, ToolTimeout};
+use crate::passwords::{self, PdfPassword};
+
+// Canonical page numbers are zero-padded to at least this many digits
+// ("page001"), widening automatically for documents with more pages.
+const MIN_PAGE_WIDTH: usize = 3;
+
+// Widest page number we ever look for when probing for existing output
+const MAX_PAGE_WIDTH: usize = 7;
+
+// Extensions a finished page can have: rendered pages use the --format extension,
+// while embedded mode writes scans out in whatever container matches their
+// original encoding.
+pub const PAGE_EXTENSIONS: &[&str] = &["png", "jpg", "webp", "tif", "jp2", "jb2"];
+
+pub fn page_number_width(page_count: usize) -> usize {
+    page_count.to_string().len().max(MIN_PAGE_WIDTH)
+}
+
+pub fn canonical_page_name(pdf_name: &str, page: usize, width: usize, extension: &str) -> String {
+    format!("{}_page{:0width$}.{}", pdf_name, page, extension, width = width)
+}
+
+// Parse the page index out of a file name of the form "{prefix}{digits}{suffix}",
+// accepting any amount of zero padding.
+fn parse_page_index(file_name: &str, prefix: &str, suffix: &str) -> Option<usize> {
+    let digits = file_name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+// List every file in output_dir named "{prefix}{digits}{suffix}", sorted by page index
+fn scan_pages(output_dir: &Path, prefix: &str, suffix: &str) -> Result<Vec<(usize, PathBuf)>> {
+    let mut pages = Vec::new();
+
+    for entry in std::fs::read_dir(output_dir)
+        .with_context(|| format!("Failed to read {}", output_dir.display()))?
+    {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+
+        if let Some(page) = parse_page_index(&file_name, prefix, suffix) {
+            pages.push((page, entry.path()));
+        }
+    }
+
+    pages.sort();
+    Ok(pages)
+}
+
+// Find the files the rasterizer actually produced for a document. pdftoppm names
+// them "{pdf_name}_page-N.{ext}" but zero-pads N to the width of the page count
+// ("-1", "-01", "-001", ...), so we parse the index instead of guessing the name.
+pub fn discover_generated_pages(output_dir: &Path, pdf_name: &str, extension: &str) -> Result<Vec<(usize, PathBuf)>> {
+    scan_pages(output_dir, &format!("{}_page-", pdf_name), &format!(".{}", extension))
+}
+
+// Probe for the first canonical page at every width we could have written it with,
+// without listing the (potentially huge) output directory.
+pub fn first_page_file(output_dir: &Path, pdf_name: &str) -> Option<PathBuf> {
+    (MIN_PAGE_WIDTH..=MAX_PAGE_WIDTH).find_map(|width| {
+        PAGE_EXTENSIONS
+            .iter()
+            .map(|extension| output_dir.join(canonical_page_name(pdf_name, 1, width, extension)))
+            .find(|path| path.exists())
+    })
+}
+
+// Locate the finished file for one page, whichever image extension it was written with
+pub fn find_page_file(output_dir: &Path, pdf_name: &str, page: usize, page_count: usize) -> Option<PathBuf> {
+    let width = page_number_width(page_count);
+    PAGE_EXTENSIONS
+        .iter()
+        .map(|extension| output_dir.join(canonical_page_name(pdf_name, page, width, extension)))
+        .find(|path| path.exists())
+}
+
+// Rename rasterizer output to "{pdf_name}_pageNNN.{ext}", where the width of NNN
+// follows the document's page count. Returns the number of pages renamed.
+pub fn rename_to_canonical(
+    output_dir: &Path,
+    pdf_name: &str,
+    generated: &[(usize, PathBuf)],
+    page_count: usize,
+    extension: &str,
+) -> Result<usize> {
+    let width = page_number_width(page_count);
+
+    for (page, old_name) in generated {
+        let new_name = output_dir.join(canonical_page_name(pdf_name, *page, width, extension));
+        std::fs::rename(old_name, &new_name)
+            .with_context(|| format!("Failed to rename {} to {}", old_name.display(), new_name.display()))?;
+    }
+
+    Ok(generated.len())
+}
+
+// Number of pages in a PDF, from pdfinfo when poppler is installed and from
+// parsing the document ourselves otherwise. A pdfinfo that has to be killed
+// is an error: a PDF that hangs poppler isn't worth parsing in-process.
+pub fn pdf_page_count(pdf_path: &Path, password: Option<&PdfPassword>, limits: &ProcessLimits) -> Result<usize> {
+    let mut command = Command::new("pdfinfo");
+    if let Some(password) = password {
+        password.add_poppler_args(&mut command);
+    }
+    match limits::run_limited_output(command.arg(pdf_path), "pdfinfo", limits) {
+        Ok(stdout) => {
+            let stdout = String::from_utf8_lossy(&stdout);
+            let pages = stdout
+                .lines()
+                .find_map(|line| line.strip_prefix("Pages:"))
+                .and_then(|count| count.trim().parse().ok());
+            if let Some(pages) = pages {
+                return Ok(pages);
+            }
+        }
+        Err(e) if e.is::<ToolTimeout>() => return Err(e),
+        Err(_) => {}
+    }
+
+    let doc = passwords::load_document(pdf_path, password)?;
+    Ok(doc.get_pages().len())
+}
+
+// Pages in 1..=page_count with no finished file on disk
+pub fn missing_pages(output_dir: &Path, pdf_name: &str, page_count: usize) -> Vec<usize> {
+    (1..=page_count)
+        .filter(|&page| find_page_file(output_dir, pdf_name, page, page_count).is_none())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_index_accepts_any_padding() {
+        for name in ["doc_page-1.png", "doc_page-01.png", "doc_page-001.png"] {
+            assert_eq!(parse_page_index(name, "doc_page-", ".png"), Some(1), "{}", name);
+        }
+        assert_eq!(parse_page_index("doc_page-1000.png", "doc_page-", ".png"), Some(1000));
+        assert_eq!(parse_page_index("doc_page-0999.png", "doc_page-", ".png"), Some(999));
+    }
+
+    #[test]
+    fn page_index_rejects_other_names() {
+        assert_eq!(parse_page_index("doc_page-.png", "doc_page-", ".png"), None);
+        assert_eq!(parse_page_index("doc_page-1a.png", "doc_page-", ".png"), None);
+        assert_eq!(parse_page_index("doc_page--1.png", "doc_page-", ".png"), None);
+        assert_eq!(parse_page_index("doc_page-1.jpg", "doc_page-", ".png"), None);
+        assert_eq!(parse_page_index("other_page-1.png", "doc_page-", ".png"), None);
+        // A document whose name extends another's must not be picked up as its pages
+        assert_eq!(parse_page_index("doc_page-2_page-1.png", "doc_page-", ".png"), None);
+    }
+
+    #[test]
+    fn page_number_width_grows_past_three_digits() {
+        assert_eq!(page_number_width(0), 3);
+        assert_eq!(page_number_width(1), 3);
+        assert_eq!(page_number_width(999), 3);
+        assert_eq!(page_number_width(1000), 4);
+        assert_eq!(page_number_width(10_000), 5);
+    }
+
+    #[test]
+    fn canonical_names_are_padded_to_the_width() {
+        assert_eq!(canonical_page_name("doc", 7, page_number_width(999), "png"), "doc_page007.png");
+        assert_eq!(canonical_page_name("doc", 7, page_number_width(1000), "png"), "doc_page0007.png");
+        assert_eq!(canonical_page_name("doc", 1000, page_number_width(1000), "png"), "doc_page1000.png");
+    }
+
+    #[test]
+    fn generated_pages_are_found_and_sorted_by_index() {
+        let dir = std::env::temp_dir().join(format!("snowdencore-pages-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "doc_page-1000.png",
+            "doc_page-0002.png",
+            "doc_page-0999.png",
+            "doc_page-0001.png",
+            "doc_page-0003.jpg",
+            "docs_page-0001.png",
+            "doc_page001.png",
+        ] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let found = discover_generated_pages(&dir, "doc", "png").unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+
+        let pages: Vec<usize> = found.iter().map(|(page, _)| *page).collect();
+        assert_eq!(pages, vec![1, 2, 999, 1000]);
+        assert_eq!(found[3].1.file_name().unwrap(), "doc_page-1000.png");
+    }
+}
@@ -0,0 +1,190 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::Command;
+
+use crate::limits::ToolTimeout;
+
+// Passwords for one document. Either may be missing: many "protected" PDFs
+// only have an owner password restricting printing and copying.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PdfPassword {
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub owner: Option<String>,
+}
+
+impl PdfPassword {
+    // The password to hand tools that take a single one (mutool, Ghostscript);
+    // the owner password unlocks everything the user password does
+    pub fn any(&self) -> Option<&str> {
+        self.owner.as_deref().or(self.user.as_deref())
+    }
+
+    // -opw/-upw, as understood by every poppler tool
+    pub fn add_poppler_args(&self, command: &mut Command) {
+        if let Some(owner) = &self.owner {
+            command.arg("-opw").arg(owner);
+        }
+        if let Some(user) = &self.user {
+            command.arg("-upw").arg(user);
+        }
+    }
+}
+
+// A map entry is either a bare string (the user password) or an object with
+// "user" and/or "owner" keys
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PasswordEntry {
+    User(String),
+    Both(PdfPassword),
+}
+
+// Passwords keyed by archive-relative path or glob pattern, loaded from a JSON
+// file such as:
+//
+//   {
+//     "leaks/report.pdf": "hunter2",
+//     "leaks/restricted/*.pdf": { "owner": "s3cret" }
+//   }
+//
+// When several entries match a document the longest (most specific) pattern wins.
+#[derive(Debug, Default)]
+pub struct PasswordMap {
+    entries: Vec<(glob::Pattern, PdfPassword)>,
+}
+
+impl PasswordMap {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read password file {}", path.display()))?;
+        let raw: BTreeMap<String, PasswordEntry> = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse password file {}", path.display()))?;
+
+        let mut entries = raw
+            .into_iter()
+            .map(|(pattern, entry)| {
+                let password = match entry {
+                    PasswordEntry::User(user) => PdfPassword { user: Some(user), owner: None },
+                    PasswordEntry::Both(password) => password,
+                };
+                let pattern = glob::Pattern::new(&pattern)
+                    .with_context(|| format!("Invalid pattern '{}' in {}", pattern, path.display()))?;
+                Ok((pattern, password))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        entries.sort_by_key(|(pattern, _)| std::cmp::Reverse(pattern.as_str().len()));
+
+        Ok(Self { entries })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    // Password for a PDF, matching patterns against its path relative to the
+    // archive directory and, failing that, against the path as given
+    pub fn lookup(&self, pdf_path: &Path, archive_dir: &Path) -> Option<&PdfPassword> {
+        let relative = pdf_path.strip_prefix(archive_dir).unwrap_or(pdf_path);
+        self.entries
+            .iter()
+            .find(|(pattern, _)| pattern.matches_path(relative) || pattern.matches_path(pdf_path))
+            .map(|(_, password)| password)
+    }
+}
+
+// Attached (as anyhow context) when a document can't be opened without a
+// password we don't have, so callers can count it apart from broken files
+#[derive(Debug)]
+pub struct EncryptedPdf {
+    pub detail: String,
+}
+
+impl std::fmt::Display for EncryptedPdf {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PDF is encrypted and no working password is known (see --passwords)")?;
+        if !self.detail.is_empty() {
+            write!(f, ": {}", self.detail)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for EncryptedPdf {}
+
+// What each rasterizer prints when it can't open a document without a
+// password: poppler, mutool and ghostscript respectively
+const PASSWORD_ERRORS: &[&str] = &["incorrect password", "cannot authenticate password", "this file requires a password"];
+
+// Mark a tool failure that says the document needs a password as EncryptedPdf;
+// anything else (including timeouts) passes through
+pub fn classify_tool_error(error: anyhow::Error) -> anyhow::Error {
+    if error.downcast_ref::<ToolTimeout>().is_some() {
+        return error;
+    }
+    let message = error.to_string();
+    let lower = message.to_lowercase();
+    if PASSWORD_ERRORS.iter().any(|password_error| lower.contains(password_error)) {
+        let detail = message.trim().to_string();
+        return error.context(EncryptedPdf { detail });
+    }
+    error
+}
+
+// Load a PDF with lopdf, decrypting it if needed. lopdf can only derive the key
+// from a user password, so we try the user password, the owner password (which
+// is sometimes set to the same value) and finally the empty password that
+// owner-restricted documents use.
+pub fn load_document(pdf_path: &Path, password: Option<&PdfPassword>) -> Result<lopdf::Document> {
+    let mut doc = lopdf::Document::load(pdf_path)
+        .with_context(|| format!("Failed to parse {}", pdf_path.display()))?;
+    if !doc.is_encrypted() {
+        return Ok(doc);
+    }
+
+    let candidates = password
+        .into_iter()
+        .flat_map(|password| [password.user.as_deref(), password.owner.as_deref()])
+        .flatten()
+        .chain([""]);
+    for candidate in candidates {
+        if doc.decrypt(candidate).is_ok() {
+            return Ok(doc);
+        }
+    }
+
+    Err(EncryptedPdf { detail: String::new() }.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::ToolFailure;
+
+    fn tool_failure(tool: &str, stderr: &str) -> anyhow::Error {
+        ToolFailure { tool: tool.to_string(), stderr: stderr.to_string(), maybe_out_of_memory: false }.into()
+    }
+
+    #[test]
+    fn password_prompts_are_encrypted() {
+        for (tool, stderr) in [
+            ("pdftoppm", "Command Line Error: Incorrect password"),
+            ("mutool", "error: cannot authenticate password: doc.pdf"),
+            ("ghostscript", "   **** This file requires a password for access."),
+        ] {
+            let error = classify_tool_error(tool_failure(tool, stderr));
+            assert!(error.downcast_ref::<EncryptedPdf>().is_some(), "{}", stderr);
+        }
+    }
+
+    #[test]
+    fn other_mentions_of_encryption_are_not() {
+        for stderr in ["Syntax Error: Couldn't read the Encrypt dictionary offset", "Syntax Error: Missing or invalid Encrypt dictionary"] {
+            let error = classify_tool_error(tool_failure("pdftoppm", stderr));
+            assert!(error.downcast_ref::<EncryptedPdf>().is_none(), "{}", stderr);
+        }
+    }
+}
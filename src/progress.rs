@@ -0,0 +1,91 @@
+use anyhow::{Context, Result};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::time::Duration;
+
+// Terminal UI for extract: one overall bar with document counts and throughput,
+// plus a line for every document currently being worked on. All log output
+// during a run goes through here so it lands above the bars instead of
+// tearing through them. The overall bar is reused for every batch (the initial
+// pass, then each batch picked up in --watch mode).
+pub struct Progress {
+    multi: MultiProgress,
+    overall: ProgressBar,
+}
+
+// A document's status line; removed from the display when dropped
+pub struct DocumentLine<'a> {
+    progress: &'a Progress,
+    bar: ProgressBar,
+}
+
+impl Progress {
+    pub fn new() -> Result<Self> {
+        let multi = MultiProgress::new();
+        let overall = multi.add(ProgressBar::new(0));
+        overall.set_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta}) {msg}")
+                .context("Failed to set progress bar template")?
+                .progress_chars("#>-"),
+        );
+
+        Ok(Self { multi, overall })
+    }
+
+    // Start counting a new batch of documents from zero
+    pub fn start_batch(&self, documents: u64) {
+        self.overall.reset();
+        self.overall.set_length(documents);
+        self.overall.set_message("");
+        self.overall.enable_steady_tick(Duration::from_millis(200));
+    }
+
+    // Print a line to stdout above the progress bars. suspend() still prints
+    // when the bars are hidden (output is not a terminal), unlike
+    // MultiProgress::println.
+    pub fn println(&self, line: impl AsRef<str>) {
+        self.multi.suspend(|| println!("{}", line.as_ref()));
+    }
+
+    // Like println, but to stderr
+    pub fn eprintln(&self, line: impl AsRef<str>) {
+        self.multi.suspend(|| eprintln!("{}", line.as_ref()));
+    }
+
+    // Show a status line for a document while it is being extracted
+    pub fn start_document(&self, status: String) -> Result<DocumentLine<'_>> {
+        let bar = self.multi.insert_before(&self.overall, ProgressBar::new_spinner());
+        bar.set_style(
+            ProgressStyle::default_spinner()
+                .template("  {spinner:.cyan} [{elapsed}] {msg}")
+                .context("Failed to set progress bar template")?,
+        );
+        bar.set_message(status);
+        bar.enable_steady_tick(Duration::from_millis(200));
+
+        Ok(DocumentLine { progress: self, bar })
+    }
+
+    // Count a finished document and refresh the throughput shown on the overall
+    // bar, given the pages and source bytes extracted so far in this batch
+    pub fn document_done(&self, batch_pages: usize, batch_bytes: u64) {
+        let elapsed = self.overall.elapsed().as_secs_f64().max(0.001);
+        self.overall.set_message(format!(
+            "{:.1} pages/s, {:.2} MB/s",
+            batch_pages as f64 / elapsed,
+            batch_bytes as f64 / (1024.0 * 1024.0) / elapsed
+        ));
+        self.overall.inc(1);
+    }
+
+    pub fn finish(&self) {
+        self.overall.finish_with_message("Complete!");
+    }
+}
+
+impl Drop for DocumentLine<'_> {
+    fn drop(&mut self) {
+        self.bar.finish_and_clear();
+        self.progress.multi.remove(&self.bar);
+    }
+}
@@ -0,0 +1,388 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use std::path::Path;
+use std::process::Command;
+
+use crate::format::OutputFormat;
+use crate::limits::{self, ProcessLimits};
+use crate::passwords::{self, PdfPassword};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    /// Use the first installed tool (pdftoppm, then mutool, then Ghostscript)
+    Auto,
+    /// poppler-utils pdftoppm
+    Pdftoppm,
+    /// MuPDF mutool draw
+    Mutool,
+    /// Ghostscript image devices
+    Ghostscript,
+}
+
+// Inclusive, 1-based range of pages to render
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageRange {
+    pub first: usize,
+    pub last: usize,
+}
+
+impl PageRange {
+    // Collapse a sorted list of page numbers into contiguous ranges
+    pub fn runs(pages: &[usize]) -> Vec<PageRange> {
+        let mut runs: Vec<PageRange> = Vec::new();
+        for &page in pages {
+            match runs.last_mut() {
+                Some(run) if run.last + 1 == page => run.last = page,
+                _ => runs.push(PageRange { first: page, last: page }),
+            }
+        }
+        runs
+    }
+
+    // Break the range into consecutive pieces of at most `max_pages` pages
+    pub fn split(&self, max_pages: usize) -> Vec<PageRange> {
+        let max_pages = max_pages.max(1);
+        (self.first..=self.last)
+            .step_by(max_pages)
+            .map(|first| PageRange { first, last: (first + max_pages - 1).min(self.last) })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RenderOptions {
+    pub dpi: u32,
+    pub format: OutputFormat,
+    // JPEG/WebP quality, 1-100
+    pub quality: u8,
+    pub gray: bool,
+    // Applied to every rasterizer invocation
+    pub limits: ProcessLimits,
+}
+
+// A tool that renders the pages of a PDF to images. Implementations must write
+// their output as "{output_dir}/{pdf_name}_page-N.{ext}", where N is the page's
+// number within the document and ext is `native_extension(format)`, so the page
+// discovery in `pages` can pick it up regardless of backend. Formats a backend
+// can't produce are rendered as PNG and transcoded afterwards.
+// `range` of None renders every page. `password` unlocks encrypted documents.
+pub trait Rasterizer: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn is_available(&self) -> bool;
+
+    fn install_hint(&self) -> &'static [&'static str];
+
+    fn native_extension(&self, format: OutputFormat) -> &'static str;
+
+    fn render(
+        &self,
+        pdf_path: &Path,
+        output_dir: &Path,
+        pdf_name: &str,
+        options: &RenderOptions,
+        range: Option<PageRange>,
+        password: Option<&PdfPassword>,
+    ) -> Result<()>;
+}
+
+fn tool_exists(program: &str, probe_arg: &str) -> bool {
+    Command::new(program).arg(probe_arg).output().is_ok()
+}
+
+// Run a rasterizer within the configured limits, reporting password failures
+// as EncryptedPdf so they aren't mistaken for broken documents
+fn run_renderer(command: &mut Command, tool: &str, options: &RenderOptions) -> Result<()> {
+    limits::run_limited(command, tool, &options.limits).map_err(passwords::classify_tool_error)
+}
+
+pub struct Pdftoppm;
+
+impl Rasterizer for Pdftoppm {
+    fn name(&self) -> &'static str {
+        "pdftoppm"
+    }
+
+    fn is_available(&self) -> bool {
+        tool_exists("pdftoppm", "-h")
+    }
+
+    fn install_hint(&self) -> &'static [&'static str] {
+        &[
+            "Ubuntu/Debian: sudo apt-get install poppler-utils",
+            "CentOS/RHEL: sudo yum install poppler-utils",
+            "macOS: brew install poppler",
+        ]
+    }
+
+    fn native_extension(&self, format: OutputFormat) -> &'static str {
+        match format {
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Tiff => "tif",
+            OutputFormat::Png | OutputFormat::Webp => "png",
+        }
+    }
+
+    fn render(
+        &self,
+        pdf_path: &Path,
+        output_dir: &Path,
+        pdf_name: &str,
+        options: &RenderOptions,
+        range: Option<PageRange>,
+        password: Option<&PdfPassword>,
+    ) -> Result<()> {
+        // pdftoppm appends "-N.{ext}" to the prefix itself
+        let output_prefix = output_dir.join(format!("{}_page", pdf_name));
+
+        let mut command = Command::new("pdftoppm");
+        match options.format {
+            OutputFormat::Jpeg => {
+                command.arg("-jpeg").arg("-jpegopt").arg(format!("quality={}", options.quality));
+            }
+            OutputFormat::Tiff => {
+                command.arg("-tiff").arg("-tiffcompression").arg("lzw");
+            }
+            OutputFormat::Png | OutputFormat::Webp => {
+                command.arg("-png");
+            }
+        }
+        if options.gray {
+            command.arg("-gray");
+        }
+        command.arg("-r").arg(options.dpi.to_string());
+        if let Some(range) = range {
+            command
+                .arg("-f")
+                .arg(range.first.to_string())
+                .arg("-l")
+                .arg(range.last.to_string());
+        }
+        if let Some(password) = password {
+            password.add_poppler_args(&mut command);
+        }
+        command.arg(pdf_path).arg(&output_prefix);
+
+        run_renderer(&mut command, "pdftoppm", options)
+    }
+}
+
+pub struct Mutool;
+
+impl Rasterizer for Mutool {
+    fn name(&self) -> &'static str {
+        "mutool"
+    }
+
+    fn is_available(&self) -> bool {
+        tool_exists("mutool", "-v")
+    }
+
+    fn install_hint(&self) -> &'static [&'static str] {
+        &[
+            "Ubuntu/Debian: sudo apt-get install mupdf-tools",
+            "CentOS/RHEL: sudo yum install mupdf",
+            "macOS: brew install mupdf-tools",
+        ]
+    }
+
+    fn native_extension(&self, _format: OutputFormat) -> &'static str {
+        "png"
+    }
+
+    fn render(
+        &self,
+        pdf_path: &Path,
+        output_dir: &Path,
+        pdf_name: &str,
+        options: &RenderOptions,
+        range: Option<PageRange>,
+        password: Option<&PdfPassword>,
+    ) -> Result<()> {
+        // mutool substitutes the document page number for %d
+        let output_pattern = output_dir.join(format!("{}_page-%d.png", pdf_name));
+
+        let mut command = Command::new("mutool");
+        command
+            .arg("draw")
+            .arg("-q")
+            .arg("-r")
+            .arg(options.dpi.to_string());
+        if options.gray {
+            command.arg("-c").arg("gray");
+        }
+        if let Some(password) = password.and_then(PdfPassword::any) {
+            command.arg("-p").arg(password);
+        }
+        command
+            .arg("-o")
+            .arg(&output_pattern)
+            .arg(pdf_path);
+        if let Some(range) = range {
+            command.arg(format!("{}-{}", range.first, range.last));
+        }
+
+        run_renderer(&mut command, "mutool", options)
+    }
+}
+
+pub struct Ghostscript;
+
+impl Rasterizer for Ghostscript {
+    fn name(&self) -> &'static str {
+        "ghostscript"
+    }
+
+    fn is_available(&self) -> bool {
+        tool_exists("gs", "--version")
+    }
+
+    fn install_hint(&self) -> &'static [&'static str] {
+        &[
+            "Ubuntu/Debian: sudo apt-get install ghostscript",
+            "CentOS/RHEL: sudo yum install ghostscript",
+            "macOS: brew install ghostscript",
+        ]
+    }
+
+    fn native_extension(&self, format: OutputFormat) -> &'static str {
+        match format {
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Tiff => "tif",
+            OutputFormat::Png | OutputFormat::Webp => "png",
+        }
+    }
+
+    fn render(
+        &self,
+        pdf_path: &Path,
+        output_dir: &Path,
+        pdf_name: &str,
+        options: &RenderOptions,
+        range: Option<PageRange>,
+        password: Option<&PdfPassword>,
+    ) -> Result<()> {
+        let extension = self.native_extension(options.format);
+        let device = match (options.format, options.gray) {
+            (OutputFormat::Jpeg, false) => "jpeg",
+            (OutputFormat::Jpeg, true) => "jpeggray",
+            (OutputFormat::Tiff, false) => "tiff24nc",
+            (OutputFormat::Tiff, true) => "tiffgray",
+            (_, false) => "png16m",
+            (_, true) => "pnggray",
+        };
+
+        // Ghostscript numbers its output from 1 no matter which page it started
+        // at, so ranged renders go to a scratch name and get renumbered below.
+        let scratch_name = format!("{}_gs-", pdf_name);
+        let output_pattern = match range {
+            Some(_) => output_dir.join(format!("{}%d.{}", scratch_name, extension)),
+            None => output_dir.join(format!("{}_page-%d.{}", pdf_name, extension)),
+        };
+
+        let mut command = Command::new("gs");
+        command
+            .arg("-q")
+            .arg("-dNOPAUSE")
+            .arg("-dBATCH")
+            .arg("-dSAFER")
+            .arg(format!("-sDEVICE={}", device))
+            .arg(format!("-r{}", options.dpi));
+        if options.format == OutputFormat::Jpeg {
+            command.arg(format!("-dJPEGQ={}", options.quality));
+        }
+        if let Some(password) = password.and_then(PdfPassword::any) {
+            command.arg(format!("-sPDFPassword={}", password));
+        }
+        if let Some(range) = range {
+            command
+                .arg(format!("-dFirstPage={}", range.first))
+                .arg(format!("-dLastPage={}", range.last));
+        }
+        command
+            .arg(format!("-sOutputFile={}", output_pattern.display()))
+            .arg(pdf_path);
+
+        run_renderer(&mut command, "gs", options)?;
+
+        if let Some(range) = range {
+            for index in 1..=(range.last - range.first + 1) {
+                let scratch = output_dir.join(format!("{}{}.{}", scratch_name, index, extension));
+                let page = output_dir.join(format!("{}_page-{}.{}", pdf_name, range.first + index - 1, extension));
+                if scratch.exists() {
+                    std::fs::rename(&scratch, &page)
+                        .with_context(|| format!("Failed to rename {} to {}", scratch.display(), page.display()))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn rasterizer_for(backend: Backend) -> Option<Box<dyn Rasterizer>> {
+    match backend {
+        Backend::Auto => None,
+        Backend::Pdftoppm => Some(Box::new(Pdftoppm)),
+        Backend::Mutool => Some(Box::new(Mutool)),
+        Backend::Ghostscript => Some(Box::new(Ghostscript)),
+    }
+}
+
+// Every concrete backend, in the order auto-detection prefers them
+pub fn all_rasterizers() -> Vec<Box<dyn Rasterizer>> {
+    vec![Box::new(Pdftoppm), Box::new(Mutool), Box::new(Ghostscript)]
+}
+
+// Resolve the requested backend to an installed rasterizer
+pub fn select_rasterizer(backend: Backend) -> Result<Box<dyn Rasterizer>> {
+    if let Some(rasterizer) = rasterizer_for(backend) {
+        if !rasterizer.is_available() {
+            let mut message = format!("{} not found. Please install it:", rasterizer.name());
+            for hint in rasterizer.install_hint() {
+                message.push_str(&format!("\n  {}", hint));
+            }
+            return Err(anyhow::anyhow!(message));
+        }
+        return Ok(rasterizer);
+    }
+
+    all_rasterizers()
+        .into_iter()
+        .find(|rasterizer| rasterizer.is_available())
+        .context("No PDF rasterizer found. Please install poppler-utils (pdftoppm), mupdf-tools (mutool) or ghostscript (gs)")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(first: usize, last: usize) -> PageRange {
+        PageRange { first, last }
+    }
+
+    #[test]
+    fn runs_collapse_consecutive_pages() {
+        assert_eq!(PageRange::runs(&[]), vec![]);
+        assert_eq!(PageRange::runs(&[5]), vec![range(5, 5)]);
+        assert_eq!(PageRange::runs(&[1, 2, 3]), vec![range(1, 3)]);
+        assert_eq!(
+            PageRange::runs(&[1, 2, 4, 7, 8, 9, 1000]),
+            vec![range(1, 2), range(4, 4), range(7, 9), range(1000, 1000)]
+        );
+    }
+
+    #[test]
+    fn split_covers_the_range_in_order() {
+        assert_eq!(range(1, 10).split(4), vec![range(1, 4), range(5, 8), range(9, 10)]);
+        assert_eq!(range(1, 8).split(4), vec![range(1, 4), range(5, 8)]);
+        assert_eq!(range(3, 3).split(4), vec![range(3, 3)]);
+        assert_eq!(range(1, 3).split(100), vec![range(1, 3)]);
+        assert_eq!(range(998, 1001).split(2), vec![range(998, 999), range(1000, 1001)]);
+    }
+
+    #[test]
+    fn split_treats_zero_as_one_page_per_job() {
+        assert_eq!(range(1, 3).split(0), vec![range(1, 1), range(2, 2), range(3, 3)]);
+    }
+}
@@ -0,0 +1,309 @@
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use image::GrayImage;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::groups::Groups;
+use crate::manifest::{self, PageRedactions, RedactionBox};
+
+// Written to the output directory by every redaction pass
+pub const REPORT_FILE: &str = "redactions.json";
+
+// Pixels darker than this (0-255 luma) can belong to a redaction box. Marker and
+// toner come out near black even on poor scans.
+const REDACTION_LEVEL: u8 = 64;
+
+// A box has to fill this much of its bounding rectangle; text, photos and
+// diagrams leave far more gaps, and scans fray the edges of real boxes
+const MIN_FILL: f64 = 0.85;
+
+// The smallest box, as a fraction of the page's width and height: about one
+// short redacted word. Rules, underlines and bullets fall below one or the other.
+const MIN_WIDTH: f64 = 0.015;
+const MIN_HEIGHT: f64 = 0.005;
+
+// A horizontal run of dark pixels, `start..end` on row `y`
+struct Run {
+    y: u32,
+    start: u32,
+    end: u32,
+}
+
+// Bounding rectangle and pixel count of a connected dark region
+struct Region {
+    min_x: u32,
+    max_x: u32,
+    min_y: u32,
+    max_y: u32,
+    pixels: u64,
+}
+
+// Find the solid black rectangles on a page image: connected regions of dark
+// pixels that nearly fill their bounding box. Regions touching the edge of the
+// page are left out, as they are the scanner's dark borders.
+pub fn find_redactions(path: &Path) -> Result<PageRedactions> {
+    let image = image::open(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .to_luma8();
+    Ok(image_redactions(&image))
+}
+
+fn image_redactions(image: &GrayImage) -> PageRedactions {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return PageRedactions { coverage: 0.0, boxes: Vec::new() };
+    }
+
+    let mut runs = Vec::new();
+    let mut row_starts = Vec::with_capacity(height as usize + 1);
+    for (y, row) in image.as_raw().chunks_exact(width as usize).enumerate() {
+        row_starts.push(runs.len());
+        let mut x = 0;
+        while x < row.len() {
+            if row[x] >= REDACTION_LEVEL {
+                x += 1;
+                continue;
+            }
+            let start = x;
+            while x < row.len() && row[x] < REDACTION_LEVEL {
+                x += 1;
+            }
+            runs.push(Run { y: y as u32, start: start as u32, end: x as u32 });
+        }
+    }
+    row_starts.push(runs.len());
+
+    // Join each run to the runs it overlaps on the row above
+    let mut regions = Groups::new(runs.len());
+    for y in 1..height as usize {
+        let (mut above, mut below) = (row_starts[y - 1], row_starts[y]);
+        while above < row_starts[y] && below < row_starts[y + 1] {
+            if runs[above].start < runs[below].end && runs[below].start < runs[above].end {
+                regions.join(above, below);
+            }
+            if runs[above].end < runs[below].end {
+                above += 1;
+            } else {
+                below += 1;
+            }
+        }
+    }
+
+    let mut bounds: HashMap<usize, Region> = HashMap::new();
+    for (index, run) in runs.iter().enumerate() {
+        let region = bounds.entry(regions.root(index)).or_insert(Region {
+            min_x: run.start,
+            max_x: run.end - 1,
+            min_y: run.y,
+            max_y: run.y,
+            pixels: 0,
+        });
+        region.min_x = region.min_x.min(run.start);
+        region.max_x = region.max_x.max(run.end - 1);
+        region.min_y = region.min_y.min(run.y);
+        region.max_y = region.max_y.max(run.y);
+        region.pixels += (run.end - run.start) as u64;
+    }
+
+    let mut boxes: Vec<RedactionBox> = bounds
+        .values()
+        .filter(|region| region.min_x > 0 && region.min_y > 0 && region.max_x < width - 1 && region.max_y < height - 1)
+        .map(|region| {
+            let redaction = RedactionBox {
+                x: region.min_x,
+                y: region.min_y,
+                width: region.max_x - region.min_x + 1,
+                height: region.max_y - region.min_y + 1,
+            };
+            (redaction, region.pixels)
+        })
+        .filter(|(redaction, pixels)| {
+            let area = redaction.width as u64 * redaction.height as u64;
+            redaction.width as f64 >= width as f64 * MIN_WIDTH
+                && redaction.height as f64 >= height as f64 * MIN_HEIGHT
+                && *pixels as f64 >= area as f64 * MIN_FILL
+        })
+        .map(|(redaction, _)| redaction)
+        .collect();
+    boxes.sort_by_key(|redaction| (redaction.y, redaction.x));
+
+    let covered: u64 = boxes.iter().map(|redaction| redaction.width as u64 * redaction.height as u64).sum();
+    PageRedactions { coverage: covered as f64 / (width as u64 * height as u64) as f64, boxes }
+}
+
+#[derive(Serialize)]
+struct PageReport<'a> {
+    page: usize,
+    file: String,
+    width: Option<u32>,
+    height: Option<u32>,
+    coverage: f64,
+    box_count: usize,
+    boxes: &'a [RedactionBox],
+}
+
+#[derive(Serialize)]
+struct DocumentReport<'a> {
+    source_path: &'a Path,
+    // Pages analysed, and how many of them have redactions
+    pages: usize,
+    redacted_pages: usize,
+    boxes: usize,
+    // Mean and highest fraction of a page covered by redactions
+    coverage: f64,
+    max_coverage: f64,
+    // Only the pages with redactions
+    redacted: Vec<PageReport<'a>>,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    generated_at_unix: u64,
+    documents: Vec<DocumentReport<'a>>,
+}
+
+pub struct RedactionSummary {
+    pub pages: usize,
+    pub redacted_pages: usize,
+    pub boxes: usize,
+}
+
+// Find the redaction boxes on every page listed in the manifests under
+// `output_dir` and write redactions.json with per-page boxes and per-document
+// statistics. Results are cached in the manifests, so later passes only look
+// at new pages.
+pub fn analyze(output_dir: &Path, generated_at_unix: u64) -> Result<RedactionSummary> {
+    let mut manifests = manifest::load_manifests(output_dir);
+
+    // Pages we can't decode ourselves (JBIG2, JPEG 2000) are left out
+    let pending: Vec<(usize, usize, PathBuf)> = manifests
+        .iter()
+        .enumerate()
+        .flat_map(|(document, loaded)| {
+            loaded
+                .manifest
+                .pages
+                .iter()
+                .enumerate()
+                .filter(|(_, entry)| entry.redactions.is_none())
+                .map(move |(index, entry)| (document, index, loaded.dir.join(&entry.file)))
+        })
+        .collect();
+    let found: Vec<(usize, usize, PageRedactions)> = pending
+        .into_par_iter()
+        .filter_map(|(document, index, path)| Some((document, index, find_redactions(&path).ok()?)))
+        .collect();
+    for (document, index, redactions) in found {
+        let loaded = &mut manifests[document];
+        loaded.manifest.pages[index].redactions = Some(redactions);
+        loaded.changed = true;
+    }
+
+    for loaded in manifests.iter().filter(|loaded| loaded.changed) {
+        manifest::write_manifest(&loaded.dir, &loaded.pdf_name, &loaded.manifest)?;
+    }
+
+    manifests.sort_by(|a, b| a.manifest.source_path.cmp(&b.manifest.source_path));
+    let mut summary = RedactionSummary { pages: 0, redacted_pages: 0, boxes: 0 };
+    let mut documents = Vec::new();
+    for loaded in &manifests {
+        let analysed: Vec<_> = loaded
+            .manifest
+            .pages
+            .iter()
+            .filter_map(|entry| Some((entry, entry.redactions.as_ref()?)))
+            .collect();
+        if analysed.is_empty() {
+            continue;
+        }
+
+        let redacted: Vec<PageReport> = analysed
+            .iter()
+            .filter(|(_, redactions)| !redactions.boxes.is_empty())
+            .map(|(entry, redactions)| PageReport {
+                page: entry.page,
+                file: manifest::relative_name(output_dir, &loaded.dir.join(&entry.file)),
+                width: entry.width,
+                height: entry.height,
+                coverage: redactions.coverage,
+                box_count: redactions.boxes.len(),
+                boxes: &redactions.boxes,
+            })
+            .collect();
+        let boxes = redacted.iter().map(|page| page.box_count).sum();
+        let total_coverage: f64 = analysed.iter().map(|(_, redactions)| redactions.coverage).sum();
+
+        summary.pages += analysed.len();
+        summary.redacted_pages += redacted.len();
+        summary.boxes += boxes;
+        documents.push(DocumentReport {
+            source_path: &loaded.manifest.source_path,
+            pages: analysed.len(),
+            redacted_pages: redacted.len(),
+            boxes,
+            coverage: total_coverage / analysed.len() as f64,
+            max_coverage: analysed.iter().map(|(_, redactions)| redactions.coverage).fold(0.0, f64::max),
+            redacted,
+        });
+    }
+
+    let report = Report { generated_at_unix, documents };
+    let report_path = output_dir.join(REPORT_FILE);
+    let json = serde_json::to_string_pretty(&report).context("Failed to serialize redactions report")?;
+    std::fs::write(&report_path, json)
+        .with_context(|| format!("Failed to write {}", report_path.display()))?;
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    fn white_page() -> GrayImage {
+        GrayImage::from_pixel(400, 300, Luma([245]))
+    }
+
+    fn fill(page: &mut GrayImage, x: u32, y: u32, width: u32, height: u32, level: u8) {
+        for py in y..y + height {
+            for px in x..x + width {
+                page.put_pixel(px, py, Luma([level]));
+            }
+        }
+    }
+
+    #[test]
+    fn finds_one_black_box_with_its_coverage() {
+        let mut page = white_page();
+        fill(&mut page, 100, 50, 80, 20, 10);
+        let redactions = image_redactions(&page);
+        assert_eq!(redactions.boxes.len(), 1);
+        let found = redactions.boxes[0];
+        assert_eq!((found.x, found.y, found.width, found.height), (100, 50, 80, 20));
+        assert!((redactions.coverage - 1600.0 / 120_000.0).abs() < 1e-9, "{}", redactions.coverage);
+    }
+
+    #[test]
+    fn blank_page_has_no_redactions() {
+        let redactions = image_redactions(&white_page());
+        assert!(redactions.boxes.is_empty());
+        assert_eq!(redactions.coverage, 0.0);
+    }
+
+    #[test]
+    fn rules_text_and_scanner_borders_are_not_redactions() {
+        let mut page = white_page();
+        // A horizontal rule, too thin to be a box
+        fill(&mut page, 20, 200, 360, 1, 0);
+        // Dark glyph-like strokes with gaps between them
+        for x in (50..150).step_by(6) {
+            fill(&mut page, x, 100, 2, 20, 0);
+        }
+        // The scanner's dark border along the left edge
+        fill(&mut page, 0, 0, 10, 300, 0);
+        assert!(image_redactions(&page).boxes.is_empty());
+    }
+}
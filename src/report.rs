@@ -0,0 +1,133 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DocumentStatus {
+    Processed,
+    Repaired,
+    Updated,
+    Skipped,
+    Failed,
+    Timeout,
+    Encrypted,
+}
+
+impl DocumentStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DocumentStatus::Processed => "processed",
+            DocumentStatus::Repaired => "repaired",
+            DocumentStatus::Updated => "updated",
+            DocumentStatus::Skipped => "skipped",
+            DocumentStatus::Failed => "failed",
+            DocumentStatus::Timeout => "timeout",
+            DocumentStatus::Encrypted => "encrypted",
+        }
+    }
+}
+
+// One line of the --report output
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportEntry {
+    pub path: PathBuf,
+    pub status: DocumentStatus,
+    pub page_count: Option<usize>,
+    pub duration_secs: f64,
+    pub source_bytes: u64,
+    pub error: Option<String>,
+    // Raw stderr of the rasterizer when it was the one that failed
+    pub stderr: Option<String>,
+    // Retry rung that rescued the document, if the first attempt failed
+    pub retry: Option<String>,
+    // Pages that couldn't be rendered even one at a time
+    pub failed_pages: Vec<usize>,
+}
+
+impl ReportEntry {
+    pub fn new(
+        path: &Path,
+        status: DocumentStatus,
+        page_count: Option<usize>,
+        duration_secs: f64,
+        source_bytes: u64,
+    ) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            status,
+            page_count,
+            duration_secs,
+            source_bytes,
+            error: None,
+            stderr: None,
+            retry: None,
+            failed_pages: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    generated_at_unix: u64,
+    documents: &'a [ReportEntry],
+}
+
+const CSV_HEADER: &str = "path,status,page_count,duration_secs,source_bytes,error,stderr,retry,failed_pages";
+
+// Quote a CSV field when it contains a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn to_csv(entries: &[ReportEntry]) -> String {
+    let mut csv = String::from(CSV_HEADER);
+    csv.push('\n');
+    for entry in entries {
+        let fields = [
+            csv_field(&entry.path.to_string_lossy()),
+            entry.status.name().to_string(),
+            entry.page_count.map(|count| count.to_string()).unwrap_or_default(),
+            format!("{:.3}", entry.duration_secs),
+            entry.source_bytes.to_string(),
+            csv_field(entry.error.as_deref().unwrap_or_default()),
+            csv_field(entry.stderr.as_deref().unwrap_or_default()),
+            csv_field(entry.retry.as_deref().unwrap_or_default()),
+            entry
+                .failed_pages
+                .iter()
+                .map(|page| page.to_string())
+                .collect::<Vec<_>>()
+                .join(" "),
+        ];
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+// Write the report as CSV when the path ends in .csv and as JSON otherwise.
+// Entries are sorted by path so reports from different runs diff cleanly; a
+// document extracted more than once (in --watch mode) keeps its latest entry.
+pub fn write_report(path: &Path, entries: &[ReportEntry], generated_at_unix: u64) -> Result<()> {
+    let latest: BTreeMap<&Path, &ReportEntry> = entries.iter().map(|entry| (entry.path.as_path(), entry)).collect();
+    let entries: Vec<ReportEntry> = latest.into_values().cloned().collect();
+
+    let is_csv = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+    let content = if is_csv {
+        to_csv(&entries)
+    } else {
+        let report = Report { generated_at_unix, documents: &entries };
+        serde_json::to_string_pretty(&report).context("Failed to serialize report")?
+    };
+
+    std::fs::write(path, content)
+        .with_context(|| format!("Failed to write report {}", path.display()))
+}
@@ -0,0 +1,78 @@
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+// Documents are rendered into "{output_dir}/.staging/{key}" and only moved
+// into the output directory once every page is finished. Keeping the staging
+// area under the output root keeps the final renames on one filesystem.
+const STAGING_DIR: &str = ".staging";
+
+pub fn staging_root(output_dir: &Path) -> PathBuf {
+    output_dir.join(STAGING_DIR)
+}
+
+// Create an empty staging directory for a document, clearing out anything a
+// crashed earlier run left behind
+pub fn prepare(output_dir: &Path, key: &str) -> Result<PathBuf> {
+    let staging = staging_root(output_dir).join(key);
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging)
+            .with_context(|| format!("Failed to clear stale staging directory {}", staging.display()))?;
+    }
+    std::fs::create_dir_all(&staging)
+        .with_context(|| format!("Failed to create staging directory {}", staging.display()))?;
+    Ok(staging)
+}
+
+// Move every finished file from the staging directory into the output directory
+// and remove the staging directory. Subdirectories (rejected/) are merged into
+// the matching directory of the output. Returns the number of files moved.
+pub fn publish(staging: &Path, output_dir: &Path) -> Result<usize> {
+    let mut moved = 0;
+
+    for entry in std::fs::read_dir(staging)
+        .with_context(|| format!("Failed to read {}", staging.display()))?
+    {
+        let entry = entry?;
+        let destination = output_dir.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            std::fs::create_dir_all(&destination)
+                .with_context(|| format!("Failed to create {}", destination.display()))?;
+            moved += publish(&entry.path(), &destination)?;
+            continue;
+        }
+        std::fs::rename(entry.path(), &destination)
+            .with_context(|| format!("Failed to move {} to {}", entry.path().display(), destination.display()))?;
+        moved += 1;
+    }
+
+    std::fs::remove_dir(staging)
+        .with_context(|| format!("Failed to remove staging directory {}", staging.display()))?;
+    Ok(moved)
+}
+
+// Paths (relative to the staging directory) of the files waiting in it
+pub fn file_names(staging: &Path) -> Result<HashSet<String>> {
+    let mut names = HashSet::new();
+    for entry in std::fs::read_dir(staging)
+        .with_context(|| format!("Failed to read {}", staging.display()))?
+    {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_dir() {
+            names.extend(file_names(&entry.path())?.into_iter().map(|file| format!("{}/{}", name, file)));
+        } else {
+            names.insert(name);
+        }
+    }
+    Ok(names)
+}
+
+pub fn discard(staging: &Path) {
+    std::fs::remove_dir_all(staging).ok();
+}
+
+// Drop the staging root once a run is over, if nothing is left in it
+pub fn cleanup(output_dir: &Path) {
+    std::fs::remove_dir(staging_root(output_dir)).ok();
+}
@@ -0,0 +1,339 @@
+use anyhow::Result;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, GrayImage, ImageBuffer, Pixel};
+use rayon::prelude::*;
+use std::path::Path;
+
+use crate::format::{self, OutputFormat};
+use crate::manifest::{PageCrop, PageTransform};
+use crate::pages;
+use snowdencore::decode;
+
+// Skew is measured on a copy of the page shrunk to this width, which keeps
+// text lines several pixels apart while making the search cheap
+const MEASURE_WIDTH: u32 = 1000;
+
+// Pixels darker than this (0-255 luma) count as ink when measuring skew
+const INK_LEVEL: u8 = 128;
+
+// Pages tilted less than this, in degrees, are left alone rather than resampled
+const MIN_SKEW: f64 = 0.1;
+
+// The coarse search steps through the allowed range this many degrees at a
+// time, then the fine search closes in around the best coarse angle
+const COARSE_STEP: f64 = 0.25;
+const FINE_STEP: f64 = 0.02;
+
+// A row or column of the border is uniform when no more than this fraction of
+// its pixels differ from the border's color by more than BORDER_TOLERANCE, so
+// dust and scanner noise don't stop the trim
+const BORDER_NOISE: f64 = 0.005;
+const BORDER_TOLERANCE: i16 = 48;
+
+// Margin left around the content, as a fraction of the page's shorter side
+const TRIM_PADDING: f64 = 0.01;
+
+// Content smaller than this fraction of the page is more likely a smudge than
+// a page worth zooming in on, so such pages aren't trimmed
+const MIN_CONTENT: f64 = 0.05;
+
+#[derive(Debug, Clone, Copy)]
+pub struct StraightenOptions {
+    // Largest tilt to correct, in degrees, when deskewing
+    pub deskew: Option<f64>,
+    pub trim: bool,
+    // For re-encoding JPEG and WebP pages
+    pub quality: u8,
+}
+
+// Parse a --max-skew value, in degrees
+pub fn parse_max_skew(value: &str) -> std::result::Result<f64, String> {
+    let degrees: f64 = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid angle '{}'", value))?;
+    if !(0.0..=45.0).contains(&degrees) {
+        return Err(format!("max skew must be between 0 and 45 degrees, got {}", degrees));
+    }
+    Ok(degrees)
+}
+
+// Score how well the ink lines up into rows when the page is assumed to be
+// tilted by `degrees`: the sum of squared ink counts per row peaks when text
+// lines fall into as few rows as possible
+fn alignment_score(ink: &[(f64, f64)], rows: usize, degrees: f64) -> f64 {
+    let slope = degrees.to_radians().tan();
+    let offset = rows as f64 / 2.0;
+    let mut counts = vec![0u32; rows * 2];
+    for &(x, y) in ink {
+        let row = (y - x * slope + offset).round();
+        if row >= 0.0 && (row as usize) < counts.len() {
+            counts[row as usize] += 1;
+        }
+    }
+    counts.iter().map(|&count| (count as f64).powi(2)).sum()
+}
+
+// The angle text lines on the page slope down to the right, in degrees,
+// within `max_skew` either way
+fn measure_skew(page: &GrayImage, max_skew: f64) -> f64 {
+    let small;
+    let page = if page.width() > MEASURE_WIDTH {
+        let height = (page.height() as u64 * MEASURE_WIDTH as u64 / page.width() as u64).max(1) as u32;
+        small = image::imageops::resize(page, MEASURE_WIDTH, height, FilterType::Triangle);
+        &small
+    } else {
+        page
+    };
+
+    let ink: Vec<(f64, f64)> = page
+        .enumerate_pixels()
+        .filter(|(_, _, pixel)| pixel.0[0] < INK_LEVEL)
+        .map(|(x, y, _)| (x as f64, y as f64))
+        .collect();
+    if ink.is_empty() {
+        return 0.0;
+    }
+
+    let best_in = |from: f64, to: f64, step: f64| {
+        let steps = ((to - from) / step).round() as i64;
+        (0..=steps)
+            .map(|index| from + index as f64 * step)
+            .map(|degrees| (degrees, alignment_score(&ink, page.height() as usize, degrees)))
+            .fold((0.0, f64::MIN), |best, candidate| if candidate.1 > best.1 { candidate } else { best })
+            .0
+    };
+    let coarse = best_in(-max_skew, max_skew, COARSE_STEP);
+    best_in((coarse - COARSE_STEP).max(-max_skew), (coarse + COARSE_STEP).min(max_skew), FINE_STEP)
+}
+
+// Rotate a page counter-clockwise by `degrees` about its centre, keeping its
+// size and filling the uncovered corners with white
+fn rotate<P: Pixel<Subpixel = u8> + 'static>(page: &ImageBuffer<P, Vec<u8>>, degrees: f64) -> ImageBuffer<P, Vec<u8>> {
+    let (width, height) = page.dimensions();
+    let channels = P::CHANNEL_COUNT as usize;
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (centre_x, centre_y) = (width as f64 / 2.0, height as f64 / 2.0);
+    let source = page.as_raw();
+    let stride = width as usize * channels;
+
+    let mut rotated = vec![255u8; source.len()];
+    for (y, row) in rotated.chunks_exact_mut(stride).enumerate() {
+        for x in 0..width as usize {
+            // Where this pixel comes from, sampled bilinearly
+            let (dx, dy) = (x as f64 + 0.5 - centre_x, y as f64 + 0.5 - centre_y);
+            let source_x = centre_x + dx * cos - dy * sin - 0.5;
+            let source_y = centre_y + dx * sin + dy * cos - 0.5;
+            if source_x < 0.0 || source_y < 0.0 || source_x > (width - 1) as f64 || source_y > (height - 1) as f64 {
+                continue;
+            }
+            let (x0, y0) = (source_x as usize, source_y as usize);
+            let (x1, y1) = ((x0 + 1).min(width as usize - 1), (y0 + 1).min(height as usize - 1));
+            let (fx, fy) = (source_x - x0 as f64, source_y - y0 as f64);
+            for channel in 0..channels {
+                let sample = |sx: usize, sy: usize| source[sy * stride + sx * channels + channel] as f64;
+                let top = sample(x0, y0) * (1.0 - fx) + sample(x1, y0) * fx;
+                let bottom = sample(x0, y1) * (1.0 - fx) + sample(x1, y1) * fx;
+                row[x * channels + channel] = (top * (1.0 - fy) + bottom * fy).round() as u8;
+            }
+        }
+    }
+    ImageBuffer::from_raw(width, height, rotated).unwrap()
+}
+
+// Whether a line of pixels is all (give or take noise) the border color
+fn is_uniform(pixels: impl Iterator<Item = u8>, color: u8, length: u32) -> bool {
+    let allowed = (length as f64 * BORDER_NOISE) as usize;
+    pixels
+        .filter(|&pixel| (pixel as i16 - color as i16).abs() > BORDER_TOLERANCE)
+        .nth(allowed)
+        .is_none()
+}
+
+fn median(mut values: Vec<u8>) -> u8 {
+    values.sort_unstable();
+    values[values.len() / 2]
+}
+
+// The part of the page left after trimming uniform borders from every side,
+// plus some padding. Borders are peeled off in layers, so a dark scanner band
+// followed by a white margin both go. None when there is nothing to trim.
+fn content_crop(page: &GrayImage) -> Option<PageCrop> {
+    let (width, height) = page.dimensions();
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let (mut top, mut bottom, mut left, mut right) = (0, height, 0, width);
+    let row = |y: u32, left: u32, right: u32| (left..right).map(move |x| page.get_pixel(x, y).0[0]);
+    let column = |x: u32, top: u32, bottom: u32| (top..bottom).map(move |y| page.get_pixel(x, y).0[0]);
+
+    loop {
+        let before = (top, bottom, left, right);
+        if top < bottom {
+            let color = median(row(top, left, right).collect());
+            while top < bottom && is_uniform(row(top, left, right), color, right - left) {
+                top += 1;
+            }
+        }
+        if top < bottom {
+            let color = median(row(bottom - 1, left, right).collect());
+            while top < bottom && is_uniform(row(bottom - 1, left, right), color, right - left) {
+                bottom -= 1;
+            }
+        }
+        if top < bottom && left < right {
+            let color = median(column(left, top, bottom).collect());
+            while left < right && is_uniform(column(left, top, bottom), color, bottom - top) {
+                left += 1;
+            }
+        }
+        if top < bottom && left < right {
+            let color = median(column(right - 1, top, bottom).collect());
+            while left < right && is_uniform(column(right - 1, top, bottom), color, bottom - top) {
+                right -= 1;
+            }
+        }
+        if top >= bottom || left >= right {
+            return None;
+        }
+        if (top, bottom, left, right) == before {
+            break;
+        }
+    }
+
+    let content = (right - left) as f64 * (bottom - top) as f64;
+    if content < width as f64 * height as f64 * MIN_CONTENT {
+        return None;
+    }
+    let padding = (width.min(height) as f64 * TRIM_PADDING) as u32;
+    let crop = PageCrop {
+        x: left.saturating_sub(padding),
+        y: top.saturating_sub(padding),
+        width: (right + padding).min(width) - left.saturating_sub(padding),
+        height: (bottom + padding).min(height) - top.saturating_sub(padding),
+    };
+    (crop != PageCrop { x: 0, y: 0, width, height }).then_some(crop)
+}
+
+// Deskew and/or trim a page and write it back to `path`. Returns None when the
+// page was left as it was, straight and without borders to trim.
+fn straighten_page(page: DynamicImage, path: &Path, format: OutputFormat, options: &StraightenOptions) -> Result<Option<PageTransform>> {
+    let (original_width, original_height) = page.dimensions();
+    let gray = page.to_luma8();
+
+    // Borders are trimmed before rotating, while they still run parallel to the
+    // page's edges; a tilted dark scanner band can't be trimmed afterwards
+    let skew = match options.deskew {
+        Some(max_skew) => measure_skew(&gray, max_skew),
+        None => 0.0,
+    };
+    let rotation_degrees = if skew.abs() >= MIN_SKEW { skew } else { 0.0 };
+    let border_crop = if options.trim { content_crop(&gray) } else { None };
+    let page = match border_crop {
+        Some(crop) => page.crop_imm(crop.x, crop.y, crop.width, crop.height),
+        None => page,
+    };
+
+    // Rotating leaves white corners, which a second trim takes off again
+    let page = match page {
+        _ if rotation_degrees == 0.0 => page,
+        DynamicImage::ImageLuma8(gray) => DynamicImage::ImageLuma8(rotate(&gray, rotation_degrees)),
+        other => DynamicImage::ImageRgb8(rotate(&other.to_rgb8(), rotation_degrees)),
+    };
+    let rotated_crop = if options.trim && rotation_degrees != 0.0 { content_crop(&page.to_luma8()) } else { None };
+    let page = match rotated_crop {
+        Some(crop) => page.crop_imm(crop.x, crop.y, crop.width, crop.height),
+        None => page,
+    };
+
+    if rotation_degrees == 0.0 && border_crop.is_none() {
+        return Ok(None);
+    }
+    format::write_image(&page, path, format, options.quality)?;
+    Ok(Some(PageTransform { original_width, original_height, border_crop, rotation_degrees, rotated_crop }))
+}
+
+// Deskew and trim every page rendered into `dir`, rewriting them in place.
+// Pages in formats we can't decode or write (JBIG2, JPEG 2000) are left alone.
+// Returns the transform applied to each page that changed.
+pub fn straighten_pages(
+    dir: &Path,
+    pdf_name: &str,
+    page_count: usize,
+    options: &StraightenOptions,
+) -> Result<Vec<(usize, PageTransform)>> {
+    let mut transforms: Vec<(usize, PageTransform)> = (1..=page_count)
+        .into_par_iter()
+        .filter_map(|page| {
+            let path = pages::find_page_file(dir, pdf_name, page, page_count)?;
+            let format = OutputFormat::of_file(&path)?;
+            let image = decode::open_image(&path).ok()?;
+            straighten_page(image, &path, format, options)
+                .map(|transform| transform.map(|transform| (page, transform)))
+                .transpose()
+        })
+        .collect::<Result<Vec<_>>>()?;
+    transforms.sort_by_key(|(page, _)| *page);
+    Ok(transforms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    // A page of dashed "text lines" 24 pixels apart, sloping down to the
+    // right by `degrees`
+    fn text_page(degrees: f64) -> GrayImage {
+        let slope = degrees.to_radians().tan();
+        let mut page = GrayImage::from_pixel(800, 600, Luma([250]));
+        for line in 0..18 {
+            let top = 60.0 + line as f64 * 24.0;
+            for x in (80..720).filter(|x| x % 14 < 10) {
+                let y = (top + x as f64 * slope).round() as u32;
+                for dy in 0..3 {
+                    page.put_pixel(x, y + dy, Luma([20]));
+                }
+            }
+        }
+        page
+    }
+
+    #[test]
+    fn measures_a_two_degree_tilt() {
+        let skew = measure_skew(&text_page(2.0), 5.0);
+        assert!((skew - 2.0).abs() <= FINE_STEP, "{}", skew);
+        let skew = measure_skew(&text_page(-2.0), 5.0);
+        assert!((skew + 2.0).abs() <= FINE_STEP, "{}", skew);
+    }
+
+    #[test]
+    fn straight_page_needs_no_deskew() {
+        assert!(measure_skew(&text_page(0.0), 5.0).abs() < MIN_SKEW);
+        assert_eq!(measure_skew(&GrayImage::from_pixel(200, 200, Luma([255])), 5.0), 0.0);
+    }
+
+    #[test]
+    fn crops_to_content_past_a_dark_band_and_white_margin() {
+        let mut page = GrayImage::from_pixel(400, 300, Luma([250]));
+        // Scanner band down the left edge, then content in the middle
+        for y in 0..300 {
+            for x in 0..15 {
+                page.put_pixel(x, y, Luma([30]));
+            }
+        }
+        for y in 100..200 {
+            for x in 120..300 {
+                page.put_pixel(x, y, Luma([if (x + y) % 5 == 0 { 0 } else { 200 }]));
+            }
+        }
+        // Padding is 1% of the shorter side
+        let crop = content_crop(&page).unwrap();
+        assert_eq!(crop, PageCrop { x: 117, y: 97, width: 186, height: 106 });
+    }
+
+    #[test]
+    fn uniform_page_is_not_cropped() {
+        assert_eq!(content_crop(&GrayImage::from_pixel(400, 300, Luma([250]))), None);
+    }
+}
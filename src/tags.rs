@@ -0,0 +1,354 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use crate::pages;
+
+// Classification levels, lowest first, with the abbreviation used in portion
+// marks. RESTRICTED has none: "(R)" is far more often a trademark sign.
+const LEVELS: &[(&str, Option<&str>)] = &[
+    ("UNCLASSIFIED", Some("U")),
+    ("RESTRICTED", None),
+    ("CONFIDENTIAL", Some("C")),
+    ("SECRET", Some("S")),
+    ("TOP SECRET", Some("TS")),
+];
+
+// Sensitive compartmented information control systems (and UK STRAP levels)
+const SCI_CONTROLS: &[&str] = &[
+    "SI", "COMINT", "TK", "TALENT KEYHOLE", "HCS", "G", "GAMMA", "ECI", "KDK", "RSV", "BUR",
+    "STRAP1", "STRAP2", "STRAP3",
+];
+
+// Dissemination controls, with abbreviations expanded
+const DISSEMINATION_CONTROLS: &[(&str, &str)] = &[
+    ("NOFORN", "NOFORN"),
+    ("NF", "NOFORN"),
+    ("ORCON", "ORCON"),
+    ("OC", "ORCON"),
+    ("IMCON", "IMCON"),
+    ("IMC", "IMCON"),
+    ("PROPIN", "PROPIN"),
+    ("PR", "PROPIN"),
+    ("RELIDO", "RELIDO"),
+    ("FOUO", "FOUO"),
+    ("LIMDIS", "LIMDIS"),
+    ("NOCONTRACT", "NOCONTRACT"),
+    ("FISA", "FISA"),
+    ("REL", "REL"),
+    ("EYES ONLY", "EYES ONLY"),
+    ("UK EYES ONLY", "UK EYES ONLY"),
+];
+
+// Releasability groups that stand for a list of countries
+const COUNTRY_GROUPS: &[(&str, &[&str])] = &[("FVEY", &["USA", "AUS", "CAN", "GBR", "NZL"])];
+
+// Classification markings found on one page, written to
+// "{pdf_name}_pageNNN.tags.json" next to the page image
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PageTags {
+    pub page: usize,
+    // Where the text came from: "text" (the PDF's text layer) and/or "ocr"
+    pub sources: Vec<String>,
+    // The highest level marked anywhere on the page
+    pub classification: Option<String>,
+    pub sci: BTreeSet<String>,
+    pub dissemination: BTreeSet<String>,
+    pub releasable_to: BTreeSet<String>,
+    // Controls we don't know, kept so nothing on the banner is lost
+    pub other_controls: BTreeSet<String>,
+    pub declassify_on: Option<String>,
+    pub derived_from: Option<String>,
+    // Every banner and portion mark as it appears on the page
+    pub markings: Vec<String>,
+}
+
+fn level_rank(level: &str) -> Option<usize> {
+    LEVELS.iter().position(|(name, _)| *name == level)
+}
+
+// Level names, highest first, for listing pages by level
+pub fn levels() -> impl Iterator<Item = &'static str> {
+    LEVELS.iter().rev().map(|(name, _)| *name)
+}
+
+// Banners and portion marks only use capitals, digits and a little punctuation;
+// requiring that keeps headings and prose out
+fn is_marking_text(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || " /,-".contains(c))
+}
+
+// Collapse runs of whitespace and drop spaces around slashes, which OCR often
+// inserts ("TOP SECRET // SI")
+fn normalize(line: &str) -> String {
+    let collapsed = line.split_whitespace().collect::<Vec<_>>().join(" ");
+    collapsed.replace(" /", "/").replace("/ ", "/")
+}
+
+// The level a marking starts with, and the rest of its first "//" segment
+fn parse_level(first: &str, abbreviated: bool) -> Option<(&'static str, &str)> {
+    for (name, abbreviation) in LEVELS.iter().rev() {
+        if first == *name || (abbreviated && Some(first) == *abbreviation) {
+            return Some((name, ""));
+        }
+        // UK style, with caveats after the level ("TOP SECRET STRAP1")
+        if let Some(rest) = first.strip_prefix(name).and_then(|rest| rest.strip_prefix(' ')) {
+            return Some((name, rest));
+        }
+    }
+    None
+}
+
+// Add one control (a "/"-separated item of a marking) to the page's tags.
+// Returns false for controls we don't recognise.
+fn add_control(tags: &mut PageTags, control: &str) -> bool {
+    if let Some(countries) = control.strip_prefix("REL TO ") {
+        for country in countries.split([',', ' ']).filter(|country| !country.is_empty()) {
+            match COUNTRY_GROUPS.iter().find(|(group, _)| *group == country) {
+                Some((_, members)) => tags.releasable_to.extend(members.iter().map(|member| member.to_string())),
+                None => {
+                    tags.releasable_to.insert(country.to_string());
+                }
+            }
+        }
+        return true;
+    }
+    if let Some((_, dissemination)) = DISSEMINATION_CONTROLS.iter().find(|(name, _)| *name == control) {
+        tags.dissemination.insert(dissemination.to_string());
+        return true;
+    }
+    // Compartments hang off their control system: "SI-G", "SI-ECI ABC"
+    let system = control.split(['-', ' ']).next().unwrap_or(control);
+    if SCI_CONTROLS.contains(&control) || SCI_CONTROLS.contains(&system) {
+        tags.sci.insert(control.to_string());
+        return true;
+    }
+    false
+}
+
+// Parse a marking such as "TOP SECRET//SI-G//ORCON/NOFORN" into `tags`.
+// Abbreviated levels ("TS//SI") are only accepted in portion marks.
+fn parse_marking(tags: &mut PageTags, marking: &str, abbreviated: bool) -> bool {
+    let mut segments = marking.split("//");
+    let first = segments.next().unwrap_or_default().trim();
+    let Some((level, caveats)) = parse_level(first, abbreviated) else {
+        return false;
+    };
+
+    // A level followed by words we don't know is a heading, not a banner
+    let mut candidate = tags.clone();
+    if !caveats.is_empty() && !add_control(&mut candidate, caveats) {
+        return false;
+    }
+    for control in segments.flat_map(|segment| segment.split('/')).map(str::trim).filter(|c| !c.is_empty()) {
+        if !add_control(&mut candidate, control) {
+            candidate.other_controls.insert(control.to_string());
+        }
+    }
+
+    let higher = match &candidate.classification {
+        Some(current) => level_rank(level) > level_rank(current),
+        None => true,
+    };
+    if higher {
+        candidate.classification = Some(level.to_string());
+    }
+    *tags = candidate;
+    true
+}
+
+// Find every classification marking in a page's text
+pub fn parse_markings(tags: &mut PageTags, text: &str) {
+    for line in text.lines() {
+        let line = normalize(line);
+
+        // Banner lines: "TOP SECRET//COMINT//REL TO USA, FVEY" or just "SECRET"
+        if is_marking_text(&line) && parse_marking(tags, &line, false) && !tags.markings.contains(&line) {
+            tags.markings.push(line.clone());
+        }
+
+        // Portion marks opening a paragraph or following a space: "(TS//SI//NF)"
+        let mut rest = line.as_str();
+        while let Some(open) = rest.find('(') {
+            let starts_word = open == 0 || rest[..open].ends_with(' ');
+            let Some(close) = rest[open..].find(')') else {
+                break;
+            };
+            let inner = &rest[open + 1..open + close];
+            let after = &rest[open + close + 1..];
+            // A lone "(C)" or "(S)" mid-sentence is far more often a list item or
+            // a copyright sign, so single letters without a control only count
+            // when they open the line and a sentence follows
+            let opens_sentence = rest.len() == line.len()
+                && open == 0
+                && after.trim_start().starts_with(|c: char| c.is_ascii_uppercase());
+            let lone_letter = inner.trim().len() == 1;
+            if starts_word
+                && (opens_sentence || !lone_letter)
+                && is_marking_text(inner)
+                && parse_marking(tags, inner, true)
+            {
+                let mark = format!("({})", inner);
+                if !tags.markings.contains(&mark) {
+                    tags.markings.push(mark);
+                }
+            }
+            rest = after;
+        }
+
+        // The classification authority block
+        for (label, field) in [("Declassify On:", &mut tags.declassify_on), ("Derived From:", &mut tags.derived_from)] {
+            if let Some(value) = line.strip_prefix(label).map(str::trim).filter(|value| !value.is_empty()) {
+                field.get_or_insert_with(|| value.to_string());
+            }
+        }
+    }
+}
+
+pub fn tags_file_name(pdf_name: &str, page: usize, width: usize) -> String {
+    pages::canonical_page_name(pdf_name, page, width, "tags.json")
+}
+
+pub fn read_page_tags(path: &Path) -> Option<PageTags> {
+    let json = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+// Parse the markings of every page in `texts` (page number, text layer, OCR
+// text) and write a tags sidecar for each into `dir`
+pub fn write_page_tags(
+    dir: &Path,
+    pdf_name: &str,
+    page_count: usize,
+    texts: &[(usize, Option<String>, Option<String>)],
+) -> Result<Vec<PageTags>> {
+    let width = pages::page_number_width(page_count);
+    let mut all_tags = Vec::new();
+
+    for (page, layer_text, ocr_text) in texts {
+        let mut tags = PageTags { page: *page, ..PageTags::default() };
+        for (source, text) in [("text", layer_text), ("ocr", ocr_text)] {
+            if let Some(text) = text.as_deref().filter(|text| !text.trim().is_empty()) {
+                tags.sources.push(source.to_string());
+                parse_markings(&mut tags, text);
+            }
+        }
+
+        let path = dir.join(tags_file_name(pdf_name, *page, width));
+        let json = serde_json::to_string_pretty(&tags).context("Failed to serialize page tags")?;
+        std::fs::write(&path, json).with_context(|| format!("Failed to write {}", path.display()))?;
+        all_tags.push(tags);
+    }
+
+    Ok(all_tags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> PageTags {
+        let mut tags = PageTags::default();
+        parse_markings(&mut tags, text);
+        tags
+    }
+
+    fn set(items: &[&str]) -> BTreeSet<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    #[test]
+    fn banner_with_controls() {
+        let tags = parse("TOP SECRET//SI-G//ORCON/NOFORN\nMemo body\nTOP SECRET//SI-G//ORCON/NOFORN");
+        assert_eq!(tags.classification.as_deref(), Some("TOP SECRET"));
+        assert_eq!(tags.sci, set(&["SI-G"]));
+        assert_eq!(tags.dissemination, set(&["ORCON", "NOFORN"]));
+        assert_eq!(tags.markings, vec!["TOP SECRET//SI-G//ORCON/NOFORN"]);
+    }
+
+    #[test]
+    fn banner_with_spaces_around_slashes() {
+        let tags = parse("  TOP SECRET // COMINT // NOFORN  ");
+        assert_eq!(tags.classification.as_deref(), Some("TOP SECRET"));
+        assert_eq!(tags.sci, set(&["COMINT"]));
+        assert_eq!(tags.markings, vec!["TOP SECRET//COMINT//NOFORN"]);
+    }
+
+    #[test]
+    fn releasability_expands_fvey() {
+        let tags = parse("SECRET//REL TO USA, FVEY");
+        assert_eq!(tags.classification.as_deref(), Some("SECRET"));
+        assert_eq!(tags.dissemination, set(&[]));
+        assert_eq!(tags.releasable_to, set(&["USA", "AUS", "CAN", "GBR", "NZL"]));
+    }
+
+    #[test]
+    fn portion_marks_raise_the_level() {
+        let tags = parse("(U) Background.\n(TS//SI//NF) The program collects.\n(C//REL TO USA, GBR) Partners.");
+        assert_eq!(tags.classification.as_deref(), Some("TOP SECRET"));
+        assert_eq!(tags.sci, set(&["SI"]));
+        assert_eq!(tags.dissemination, set(&["NOFORN"]));
+        assert_eq!(tags.releasable_to, set(&["USA", "GBR"]));
+        assert_eq!(tags.markings, vec!["(U)", "(TS//SI//NF)", "(C//REL TO USA, GBR)"]);
+    }
+
+    #[test]
+    fn single_letter_portion_mark_opening_a_sentence() {
+        let tags = parse("(S) The program collects metadata in bulk.");
+        assert_eq!(tags.classification.as_deref(), Some("SECRET"));
+        assert_eq!(tags.markings, vec!["(S)"]);
+    }
+
+    #[test]
+    fn multi_letter_portion_mark_alone() {
+        let tags = parse("(TS) Summary of the collection.");
+        assert_eq!(tags.classification.as_deref(), Some("TOP SECRET"));
+    }
+
+    #[test]
+    fn lettered_clauses_are_not_portion_marks() {
+        let tags = parse("The order requires (A) a warrant, (B) a review and (C) a report.\n(S) the Secretary shall certify");
+        assert_eq!(tags.classification, None);
+        assert!(tags.markings.is_empty());
+    }
+
+    #[test]
+    fn copyright_sign_is_not_a_portion_mark() {
+        let tags = parse("Copyright (C) 2013 Example Corp.\n(R) Registered trademark");
+        assert_eq!(tags.classification, None);
+    }
+
+    #[test]
+    fn portion_marks_must_start_a_word() {
+        let tags = parse("see section 4(S//NF) above");
+        assert_eq!(tags.classification, None);
+    }
+
+    #[test]
+    fn headings_are_not_banners() {
+        let tags = parse("SECRET SERVICE AGENTS\nTop Secret clearance required");
+        assert_eq!(tags.classification, None);
+    }
+
+    #[test]
+    fn uk_caveats_after_the_level() {
+        let tags = parse("TOP SECRET STRAP1");
+        assert_eq!(tags.classification.as_deref(), Some("TOP SECRET"));
+        assert_eq!(tags.sci, set(&["STRAP1"]));
+    }
+
+    #[test]
+    fn unknown_controls_are_kept() {
+        let tags = parse("SECRET//XYZZY");
+        assert_eq!(tags.classification.as_deref(), Some("SECRET"));
+        assert_eq!(tags.other_controls, set(&["XYZZY"]));
+    }
+
+    #[test]
+    fn authority_block() {
+        let tags = parse("Derived From: NSA/CSSM 1-52\nDeclassify On: 20370101\nDeclassify On: 20400101");
+        assert_eq!(tags.derived_from.as_deref(), Some("NSA/CSSM 1-52"));
+        assert_eq!(tags.declassify_on.as_deref(), Some("20370101"));
+    }
+}
@@ -0,0 +1,120 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::process::Command;
+
+use crate::limits::{self, ProcessLimits};
+use crate::pages;
+use crate::passwords::{self, PdfPassword};
+use crate::rasterizer::PageRange;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextExtractor {
+    // poppler-utils pdftotext, keeping the physical layout of the page
+    Pdftotext,
+    // lopdf's content stream text extraction, for when poppler isn't installed
+    Native,
+}
+
+impl TextExtractor {
+    pub fn detect() -> Self {
+        if Command::new("pdftotext").arg("-v").output().is_ok() {
+            TextExtractor::Pdftotext
+        } else {
+            TextExtractor::Native
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TextExtractor::Pdftotext => "pdftotext",
+            TextExtractor::Native => "native",
+        }
+    }
+
+    // Text of each page in `range`, in page order. `limits` applies to pdftotext.
+    pub fn page_texts(
+        &self,
+        pdf_path: &Path,
+        range: PageRange,
+        password: Option<&PdfPassword>,
+        limits: &ProcessLimits,
+    ) -> Result<Vec<String>> {
+        match self {
+            TextExtractor::Pdftotext => {
+                let mut command = Command::new("pdftotext");
+                if let Some(password) = password {
+                    password.add_poppler_args(&mut command);
+                }
+                command
+                    .arg("-layout")
+                    .arg("-enc")
+                    .arg("UTF-8")
+                    .arg("-f")
+                    .arg(range.first.to_string())
+                    .arg("-l")
+                    .arg(range.last.to_string())
+                    .arg(pdf_path)
+                    .arg("-");
+                let stdout = limits::run_limited_output(&mut command, "pdftotext", limits)?;
+
+                // pdftotext ends every page with a form feed
+                let stdout = String::from_utf8_lossy(&stdout);
+                Ok(stdout
+                    .split('\x0c')
+                    .take(range.last - range.first + 1)
+                    .map(str::to_string)
+                    .collect())
+            }
+            TextExtractor::Native => {
+                let doc = passwords::load_document(pdf_path, password)?;
+                Ok((range.first..=range.last)
+                    .map(|page| doc.extract_text(&[page as u32]).unwrap_or_default())
+                    .collect())
+            }
+        }
+    }
+}
+
+// Pages in `range` whose text layer is empty, i.e. image-only scans
+pub fn pages_without_text(
+    extractor: TextExtractor,
+    pdf_path: &Path,
+    range: PageRange,
+    password: Option<&PdfPassword>,
+    limits: &ProcessLimits,
+) -> Result<Vec<usize>> {
+    let texts = extractor.page_texts(pdf_path, range, password, limits)?;
+    Ok((range.first..=range.last)
+        .filter(|&page| texts.get(page - range.first).is_none_or(|text| text.trim().is_empty()))
+        .collect())
+}
+
+// Write "{pdf_name}_pageNNN.txt" with the text layer of every page that has an
+// image in `output_dir`, next to it: pages a repair didn't render or that were
+// set aside as blank get no sidecar. Returns the number of sidecars written.
+pub fn write_text_sidecars(
+    extractor: TextExtractor,
+    pdf_path: &Path,
+    output_dir: &Path,
+    pdf_name: &str,
+    page_count: usize,
+    password: Option<&PdfPassword>,
+    limits: &ProcessLimits,
+) -> Result<usize> {
+    let width = pages::page_number_width(page_count);
+    let range = PageRange { first: 1, last: page_count };
+    let texts = extractor.page_texts(pdf_path, range, password, limits)?;
+
+    let mut written = 0;
+    for (page, text) in (range.first..).zip(&texts) {
+        if pages::find_page_file(output_dir, pdf_name, page, page_count).is_none() {
+            continue;
+        }
+        let path = output_dir.join(pages::canonical_page_name(pdf_name, page, width, "txt"));
+        std::fs::write(&path, text)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        written += 1;
+    }
+
+    Ok(written)
+}
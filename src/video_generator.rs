@@ -6,10 +6,14 @@ use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
 use std::fs::create_dir_all;
 use std::process::Command;
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
 use image::GenericImageView;
 use rayon::prelude::*;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use snowdencore::catalog;
+use snowdencore::decode::open_image;
 
 #[derive(Debug, Clone, ValueEnum)]
 enum VideoFormat {
@@ -33,7 +37,7 @@ struct Args {
     #[arg(short = 'o', long)]
     output_video: PathBuf,
     
-    /// Directory containing image files (.png, .jpg, .jpeg)
+    /// Directory containing image files (.png, .jpg, .jpeg, .webp, .tif, .tiff)
     #[arg(long, default_value = "Snowden-PNGs")]
     image_dir: PathBuf,
     
@@ -44,6 +48,23 @@ struct Args {
     /// Video format: desktop (16:9) or mobile (9:16 with stacked images)
     #[arg(long, value_enum)]
     format: VideoFormat,
+    
+    /// Only use pages marked with these classification levels by `extract --tags`, e.g. top-secret,secret (unmarked: tagged pages without markings)
+    #[arg(long, value_delimiter = ',')]
+    classification: Vec<String>,
+    
+    /// Only use pages at least this much covered by redaction boxes (0-1), as found by `extract --redactions`
+    #[arg(long)]
+    min_redaction: Option<f64>,
+    
+    /// SQLite catalog written by `extract` to select pages from instead of walking --image-dir (only pages extract wrote a manifest for)
+    #[arg(long)]
+    catalog: Option<PathBuf>,
+    
+    /// Only use catalog pages matching this SQL condition on the pages and documents tables, e.g. "pages.text LIKE '%PRISM%'" (uses catalog.sqlite in --image-dir unless --catalog is given).
+    /// The condition is pasted into the query as trusted SQL; the catalog is opened read-only while it runs
+    #[arg(long = "where")]
+    query: Option<String>,
 }
 
 fn get_mp3_duration(mp3_path: &Path) -> Result<f64> {
@@ -70,30 +91,160 @@ fn get_mp3_duration(mp3_path: &Path) -> Result<f64> {
     Ok(duration)
 }
 
-fn find_image_files(image_dir: &Path) -> Result<Vec<PathBuf>> {
+fn read_manifest(manifest_path: &Path) -> Option<serde_json::Value> {
+    let json = std::fs::read_to_string(manifest_path).ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+// Pages the extractor tagged as blank (--blank-action tag), listed in the
+// "blank_pages" of the manifests next to them
+fn tagged_blank_pages(dir: &Path, manifest: &serde_json::Value) -> Vec<PathBuf> {
+    manifest["blank_pages"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|blank| blank["file"].as_str())
+        .map(|file| dir.join(file))
+        .collect()
+}
+
+// "TOP SECRET" for "top-secret", "Top_Secret" or "TOP SECRET"
+fn normalize_level(level: &str) -> String {
+    level.trim().to_uppercase().replace(['-', '_'], " ")
+}
+
+// The classification level of each page `extract --tags` looked at, "UNMARKED"
+// for pages it found no markings on
+fn page_classifications(dir: &Path, manifest: &serde_json::Value) -> Vec<(PathBuf, String)> {
+    manifest["pages"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|page| page["tags_file"].is_string())
+        .filter_map(|page| {
+            let file = page["file"].as_str()?;
+            let level = page["classification"].as_str().unwrap_or("UNMARKED");
+            Some((dir.join(file), level.to_string()))
+        })
+        .collect()
+}
+
+// The fraction of each page `extract --redactions` found covered by redaction boxes
+fn page_redaction_coverage(dir: &Path, manifest: &serde_json::Value) -> Vec<(PathBuf, f64)> {
+    manifest["pages"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|page| Some((dir.join(page["file"].as_str()?), page["redactions"]["coverage"].as_f64()?)))
+        .collect()
+}
+
+fn find_image_files(image_dir: &Path, classification: &[String], min_redaction: Option<f64>) -> Result<Vec<PathBuf>> {
     let mut image_files = Vec::new();
+    let mut blank_pages = HashSet::new();
+    let mut classifications = HashMap::new();
+    let mut redaction_coverage = HashMap::new();
+    
+    // Skip hidden directories such as the extractor's in-progress ".staging" area,
+    // and the "rejected" folders it moves blank pages into
+    let walker = WalkDir::new(image_dir)
+        .into_iter()
+        .filter_entry(|e| {
+            let name = e.file_name().to_string_lossy();
+            e.depth() == 0 || !(name.starts_with('.') || (e.file_type().is_dir() && name == "rejected"))
+        });
     
-    for entry in WalkDir::new(image_dir).into_iter().filter_map(|e| e.ok()) {
+    for entry in walker.filter_map(|e| e.ok()) {
         if entry.file_type().is_file() {
+            if entry.file_name().to_string_lossy().ends_with(".manifest.json") {
+                if let (Some(dir), Some(manifest)) = (entry.path().parent(), read_manifest(entry.path())) {
+                    blank_pages.extend(tagged_blank_pages(dir, &manifest));
+                    classifications.extend(page_classifications(dir, &manifest));
+                    redaction_coverage.extend(page_redaction_coverage(dir, &manifest));
+                }
+                continue;
+            }
             if let Some(extension) = entry.path().extension() {
                 let ext = extension.to_string_lossy().to_lowercase();
-                if ext == "png" || ext == "jpg" || ext == "jpeg" {
+                if ["png", "jpg", "jpeg", "webp", "tif", "tiff"].contains(&ext.as_str()) {
                     image_files.push(entry.path().to_path_buf());
                 }
             }
         }
     }
     
+    image_files.retain(|path| !blank_pages.contains(path));
+    
+    // Pages that were never tagged have no known level and are left out
+    if !classification.is_empty() {
+        let wanted: HashSet<String> = classification.iter().map(|level| normalize_level(level)).collect();
+        image_files.retain(|path| classifications.get(path).is_some_and(|level| wanted.contains(level)));
+    }
+    if let Some(min_redaction) = min_redaction {
+        image_files.retain(|path| redaction_coverage.get(path).is_some_and(|coverage| *coverage >= min_redaction));
+    }
+    
+    drop_hard_links(&mut image_files);
     Ok(image_files)
 }
 
+// Select pages from the catalog with the same filters find_image_files applies,
+// plus any --where condition. Returns the pages and a description of the selection.
+fn select_catalog_pages(catalog: &catalog::Catalog, args: &Args) -> Result<(Vec<PathBuf>, String)> {
+    let mut conditions = Vec::new();
+    let mut values = Vec::new();
+    let mut selection = Vec::new();
+    
+    // Tagged pages without markings count as "UNMARKED", untagged pages as nothing
+    if !args.classification.is_empty() {
+        let wanted: Vec<String> = args.classification.iter().map(|level| normalize_level(level)).collect();
+        conditions.push(format!(
+            "COALESCE(pages.classification, CASE WHEN pages.tags IS NOT NULL THEN 'UNMARKED' END) IN ({})",
+            vec!["?"; wanted.len()].join(", ")
+        ));
+        selection.push(format!("classification: {}", wanted.join(", ")));
+        values.extend(wanted.into_iter().map(catalog::Value::Text));
+    }
+    if let Some(min_redaction) = args.min_redaction {
+        conditions.push("pages.redaction_coverage >= ?".to_string());
+        values.push(catalog::Value::Real(min_redaction));
+        selection.push(format!("min redaction: {}", min_redaction));
+    }
+    if let Some(query) = &args.query {
+        conditions.push(format!("({})", query));
+        selection.push(format!("where: {}", query));
+    }
+    
+    let condition = if conditions.is_empty() { "1".to_string() } else { conditions.join(" AND ") };
+    let mut image_files = catalog.select_pages(&condition, values)?;
+    drop_hard_links(&mut image_files);
+    let selection = if selection.is_empty() { "all pages".to_string() } else { selection.join("; ") };
+    Ok((image_files, selection))
+}
+
+// Pages hard-linked together by `extract --dedupe hardlink` are one image
+#[cfg(unix)]
+fn drop_hard_links(image_files: &mut Vec<PathBuf>) {
+    use std::os::unix::fs::MetadataExt;
+
+    let mut seen = HashSet::new();
+    image_files.retain(|path| match std::fs::metadata(path) {
+        Ok(metadata) => seen.insert((metadata.dev(), metadata.ino())),
+        Err(_) => true,
+    });
+}
+
+// Hard links can't be told apart without inode numbers, so they stay in
+#[cfg(not(unix))]
+fn drop_hard_links(_image_files: &mut Vec<PathBuf>) {}
+
 fn validate_image_files(image_files: Vec<PathBuf>) -> Vec<PathBuf> {
     println!("Validating {} image files...", image_files.len());
     
     let valid_files: Vec<PathBuf> = image_files
         .par_iter()
         .filter_map(|path| {
-            match image::open(path) {
+            match open_image(path) {
                 Ok(_) => Some(path.clone()),
                 Err(_) => {
                     println!("Filtering out corrupted image: {}", path.display());
@@ -187,7 +338,7 @@ fn create_mobile_stacked_frame(images: &[PathBuf], frame_number: u32, temp_dir:
     for (i, image_path) in images.iter().enumerate() {
         if i >= 3 { break; } // Only use first 3 images
         
-        let img = match image::open(image_path) {
+        let img = match open_image(image_path) {
             Ok(img) => img,
             Err(e) => {
                 println!("Warning: Skipping corrupted image {}: {}", image_path.display(), e);
@@ -234,7 +385,7 @@ fn create_video_precise_timing(
     framerate: u32,
     format: VideoFormat,
     mp3_duration: f64,
-) -> Result<()> {
+) -> Result<Vec<PathBuf>> {
     let (width, height) = match format {
         VideoFormat::Mobile => (1080u32, 1920u32),
         VideoFormat::Desktop => (1280u32, 720u32),
@@ -292,7 +443,7 @@ fn create_video_precise_timing(
                 // Desktop format - single image per frame
                 if let Some(png_path) = job.images.first() {
                     // Load and resize image
-                    let resized = match image::open(png_path) {
+                    let resized = match open_image(png_path) {
                         Ok(img) => img.resize_exact(width, height, image::imageops::FilterType::Lanczos3),
                         Err(e) => {
                             return (i, Err(anyhow::anyhow!("Corrupted image {}: {}", png_path.display(), e)));
@@ -325,6 +476,17 @@ fn create_video_precise_timing(
     
     println!("Renumbering {} successful frames consecutively...", successful_frames.len());
     
+    // The images that made it into the video, in order of first appearance
+    let mut used_images = Vec::new();
+    let mut seen_images = HashSet::new();
+    for (original_index, _) in &successful_frames {
+        for image in &frame_jobs[*original_index].images {
+            if seen_images.insert(image) {
+                used_images.push(image.clone());
+            }
+        }
+    }
+    
     // Renumber successful frames to be consecutive (0, 1, 2, 3...)
     for (new_index, (_original_index, old_path)) in successful_frames.iter().enumerate() {
         let new_path = temp_dir.join(format!("frame_{:06}.png", new_index));
@@ -362,7 +524,7 @@ fn create_video_precise_timing(
     println!("Cleaning up temporary frames...");
     std::fs::remove_dir_all(&temp_dir).ok();
     
-    Ok(())
+    Ok(used_images)
 }
 
 fn main() -> Result<()> {
@@ -389,10 +551,41 @@ fn main() -> Result<()> {
     let mp3_duration = get_mp3_duration(&args.song_path)?;
     println!("MP3 duration: {:.2} seconds", mp3_duration);
     
-    // Find all image files
-    println!("Finding image files...");
-    let image_files = find_image_files(&args.image_dir)?;
-    println!("Found {} image files", image_files.len());
+    // Select pages from the catalog only when asked to, otherwise find all image
+    // files: the catalog doesn't know images extract wrote no manifest for
+    let catalog_path = match (&args.catalog, &args.query) {
+        (Some(path), _) => Some(path.clone()),
+        (None, Some(_)) => Some(args.image_dir.join(catalog::DEFAULT_FILE)),
+        (None, None) => None,
+    };
+    if let Some(path) = catalog_path.as_ref().filter(|path| !path.exists()) {
+        return Err(anyhow::anyhow!("Catalog not found: {} (run `extract` first or pass --catalog)", path.display()));
+    }
+    let (image_files, selection) = match &catalog_path {
+        Some(path) => {
+            println!("Selecting pages from catalog {}...", path.display());
+            select_catalog_pages(&catalog::Catalog::open_read_only(path)?, &args)?
+        }
+        _ => {
+            println!("Finding image files...");
+            (find_image_files(&args.image_dir, &args.classification, args.min_redaction)?, String::new())
+        }
+    };
+    let mut filters = Vec::new();
+    if !args.classification.is_empty() {
+        filters.push(format!("marked {}", args.classification.join(", ")));
+    }
+    if let Some(min_redaction) = args.min_redaction {
+        filters.push(format!("at least {:.0}% redacted", min_redaction * 100.0));
+    }
+    if let Some(query) = &args.query {
+        filters.push(format!("where {}", query));
+    }
+    if filters.is_empty() {
+        println!("Found {} image files", image_files.len());
+    } else {
+        println!("Found {} image files {}", image_files.len(), filters.join(" and "));
+    }
     
     if image_files.is_empty() {
         return Err(anyhow::anyhow!("No image files found in {}", args.image_dir.display()));
@@ -407,7 +600,7 @@ fn main() -> Result<()> {
     
     // Create the video
     println!("Generating video...");
-    create_video_precise_timing(
+    let used_images = create_video_precise_timing(
         valid_image_files,
         args.jump_cut_seconds,
         &args.song_path,
@@ -419,6 +612,17 @@ fn main() -> Result<()> {
     
     println!("✓ Video created successfully: {}", args.output_video.display());
     
+    // Only recording the video writes to the catalog
+    if let Some(path) = &catalog_path {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let recorded = catalog::Catalog::open(path)
+            .and_then(|mut catalog| catalog.record_video(&args.output_video, &selection, &used_images, now));
+        match recorded {
+            Ok(()) => println!("Recorded {} pages used in the catalog", used_images.len()),
+            Err(e) => eprintln!("Failed to record the video in the catalog: {}", e),
+        }
+    }
+    
     // Show final stats
     let output_size = std::fs::metadata(&args.output_video)?
         .len() as f64 / (1024.0 * 1024.0);
@@ -0,0 +1,110 @@
+use anyhow::{Context, Result};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+// A PDF must go this long without filesystem events before we consider it
+// fully written; copies into the archive arrive as a stream of modify events.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+// How often pending files are checked against SETTLE_TIME
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+pub fn is_pdf(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.to_string_lossy().to_lowercase() == "pdf")
+}
+
+// Filesystem notifications for every PDF created or modified under a directory.
+// Start it before the initial pass so documents dropped in while that pass is
+// running are picked up afterwards.
+pub struct ArchiveWatcher {
+    // Kept alive for as long as we want events
+    _watcher: notify::RecommendedWatcher,
+    events: Receiver<notify::Result<notify::Event>>,
+    // The archive directory as given, which batches are reported under, and
+    // resolved, which some backends report event paths under instead
+    archive_dir: PathBuf,
+    canonical_dir: PathBuf,
+}
+
+impl ArchiveWatcher {
+    pub fn new(archive_dir: &Path) -> Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender).context("Failed to create file watcher")?;
+        watcher
+            .watch(archive_dir, RecursiveMode::Recursive)
+            .with_context(|| format!("Failed to watch {}", archive_dir.display()))?;
+        let canonical_dir = archive_dir
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve {}", archive_dir.display()))?;
+
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            archive_dir: archive_dir.to_path_buf(),
+            canonical_dir,
+        })
+    }
+
+    // An event path under the archive directory as given, so output names and
+    // manifests match the ones the initial pass wrote. Backends report paths
+    // made absolute, or with symlinks resolved, so compare resolved paths.
+    fn rebase(&self, path: PathBuf) -> PathBuf {
+        let resolved = match (path.parent().and_then(|parent| parent.canonicalize().ok()), path.file_name()) {
+            (Some(parent), Some(name)) => parent.join(name),
+            _ => return path,
+        };
+        match resolved.strip_prefix(&self.canonical_dir) {
+            Ok(relative) => self.archive_dir.join(relative),
+            Err(_) => path,
+        }
+    }
+
+    // Block forever, calling `on_batch` with every group of PDFs that have
+    // settled since the last call, sorted by path
+    pub fn run(&self, mut on_batch: impl FnMut(Vec<PathBuf>)) -> Result<()> {
+        let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+
+        loop {
+            match self.events.recv_timeout(POLL_INTERVAL) {
+                Ok(Ok(event)) => {
+                    if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                        for path in event.paths.into_iter().filter(|path| is_pdf(path)) {
+                            pending.insert(self.rebase(path), Instant::now());
+                        }
+                    }
+                }
+                Ok(Err(e)) => eprintln!("  ! File watcher error: {}", e),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(anyhow::anyhow!("File watcher stopped unexpectedly"));
+                }
+            }
+
+            let settled: Vec<PathBuf> = pending
+                .iter()
+                .filter(|(_, last_event)| last_event.elapsed() >= SETTLE_TIME)
+                .map(|(path, _)| path.clone())
+                .collect();
+            if settled.is_empty() {
+                continue;
+            }
+
+            // Files that were moved away or deleted again in the meantime are dropped
+            let mut ready = Vec::new();
+            for path in settled {
+                pending.remove(&path);
+                if path.is_file() {
+                    ready.push(path);
+                }
+            }
+            ready.sort();
+            if !ready.is_empty() {
+                on_batch(ready);
+            }
+        }
+    }
+}